@@ -1,33 +1,53 @@
 use cache::CacheCmd;
+use completions::CompletionsCmd;
+use config::ConfigCmd;
+use doctor::DoctorCmd;
 use env::EnvCmd;
+use exec::ExecCmd;
 use hook::HookCmd;
 use install::InstallCmd;
 use manage::ManageCmd;
+use outdated::OutdatedCmd;
+use prompt::PromptCmd;
+use relocate::RelocateCmd;
+use shell::ShellCmd;
+use state::StateCmd;
 use use_::UseCmd;
 use which::WhichCmd;
 
+pub(crate) use hook::Shell;
+pub use output::OutputFormat;
+
 mod cache;
+mod completions;
+mod config;
+mod doctor;
 mod env;
+mod exec;
 mod hook;
 mod install;
 mod manage;
+mod outdated;
+mod output;
+mod prompt;
+mod relocate;
+mod shell;
+mod state;
 mod use_;
 mod which;
 
-// TODO: it would be cool to support migrations in here eventually, for example
-// initializing an environment on version 0.79 and then upgrading it to 0.80
-//
-// for now, that will remain a manual process.
-
 #[derive(Debug, clap::Subcommand)]
 #[allow(clippy::large_enum_variant)]
 pub enum Command {
     /// Install a given version of the Penumbra software suite.
     #[clap(display_order = 100, visible_alias = "i")]
     Install(InstallCmd),
-    /// Set a Penumbra environment as active in the current shell.
+    /// Set a Penumbra environment as active globally, in every shell.
     #[clap(display_order = 200, visible_alias = "u")]
     Use(UseCmd),
+    /// Set a Penumbra environment as active in the current shell only.
+    #[clap(display_order = 250, visible_alias = "s")]
+    Shell(ShellCmd),
     /// Manage the cache of installed Penumbra versions.
     #[clap(display_order = 300, visible_alias = "c")]
     Cache(CacheCmd),
@@ -38,16 +58,77 @@ pub enum Command {
     /// Display information about the active Penumbra environment.
     #[clap(display_order = 600, visible_alias = "w")]
     Which(WhichCmd),
+    /// Run a command in a Penumbra environment, without activating it.
+    #[clap(display_order = 620, visible_alias = "x")]
+    Exec(ExecCmd),
+    /// Report binary environments whose pinned version is behind the newest
+    /// installed or available release, exiting non-zero if any are.
+    #[clap(display_order = 650)]
+    Outdated(OutdatedCmd),
+    /// Output a prompt segment describing the effective Penumbra environment.
+    #[clap(display_order = 680)]
+    Prompt(PromptCmd),
     /// Output the necessary environment variables to use penv.
     #[clap(display_order = 700, visible_alias = "e")]
     Env(EnvCmd),
     /// Output the necessary shell script to hook penv into the active shell.
     #[clap(display_order = 800, visible_alias = "h")]
     Hook(HookCmd),
+    /// Output shell completions for penv.
+    #[clap(display_order = 850)]
+    Completions(CompletionsCmd),
     /// Unset the currently active environment.
     #[clap(display_order = 900, visible_alias = "d")]
     Deactivate,
+    /// Get and set the defaults penv uses for command-line flags.
+    #[clap(display_order = 910)]
+    Config(ConfigCmd),
+    /// Move the penv home to another directory, updating the environments in it.
+    #[clap(display_order = 915)]
+    Relocate(RelocateCmd),
+    /// Check the penv home and the shell setup for problems, and optionally repair them.
+    #[clap(display_order = 918)]
+    Doctor(DoctorCmd),
+    /// Inspect penv's own state files.
+    #[clap(display_order = 920)]
+    State(StateCmd),
     /// Reset all penv state.
     #[clap(display_order = 950)]
     UnsafeResetAll,
 }
+
+impl Command {
+    /// Whether the command changes penv's state, and so has to hold the lock on the penv
+    /// home while it runs.
+    pub fn modifies_state(&self) -> bool {
+        match self {
+            Command::Cache(cache_cmd) => !matches!(
+                cache_cmd.subcmd,
+                cache::CacheTopSubCmd::List(_) | cache::CacheTopSubCmd::Available(_)
+            ),
+            Command::Manage(manage_cmd) => !matches!(
+                manage_cmd.subcmd,
+                manage::ManageTopSubCmd::Info(_) | manage::ManageTopSubCmd::List(_)
+            ),
+            Command::Config(config_cmd) => matches!(
+                config_cmd.subcmd,
+                config::ConfigTopSubCmd::Set(_) | config::ConfigTopSubCmd::Unset(_)
+            ),
+            Command::Doctor(doctor_cmd) => doctor_cmd.fix,
+            Command::Install(_)
+            | Command::Use(_)
+            | Command::Deactivate
+            | Command::Relocate(_)
+            | Command::UnsafeResetAll => true,
+            Command::Shell(_)
+            | Command::State(_)
+            | Command::Which(_)
+            | Command::Exec(_)
+            | Command::Outdated(_)
+            | Command::Prompt(_)
+            | Command::Env(_)
+            | Command::Hook(_)
+            | Command::Completions(_) => false,
+        }
+    }
+}
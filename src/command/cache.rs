@@ -1,11 +1,18 @@
+use std::sync::Arc;
+
 use anyhow::{anyhow, Result};
 use camino::Utf8PathBuf;
 use clap::value_parser;
-// TODO: better handle colorized text with flags
 use colored::Colorize;
+use serde::Serialize;
 
-use crate::penv::environment::EnvironmentTrait;
-use crate::penv::release::{RepoOrVersion, RepoOrVersionReq};
+use super::OutputFormat;
+use crate::penv::config::Config;
+use crate::penv::environment::{Environment, EnvironmentTrait};
+use crate::penv::release::{
+    InstalledRelease, Release, ReleaseChannel, RepoOrVersion, RepoOrVersionReq,
+};
+use crate::penv::Penv;
 
 #[derive(Debug, clap::Parser)]
 pub struct CacheCmd {
@@ -31,12 +38,32 @@ pub enum CacheTopSubCmd {
 pub struct ListCmd {
     /// Only list versions matching the given semver version requirement.
     required_version: Option<RepoOrVersionReq>,
+    /// Only list versions installed from the given GitHub repository.
+    #[clap(long)]
+    repository_name: Option<String>,
+    /// The release channel a "latest" version requirement resolves against.
+    ///
+    /// "stable" only considers stable releases, "prerelease" also considers prereleases,
+    /// and "all" also considers draft releases. Defaults to the `channel` setting.
+    #[clap(long, value_enum)]
+    channel: Option<ReleaseChannel>,
 }
 
 #[derive(Debug, Clone, clap::Parser)]
 pub struct AvailableCmd {
     /// Only list versions matching the given semver version requirement.
     required_version: Option<RepoOrVersionReq>,
+    /// The GitHub repository to list releases of.
+    ///
+    /// Defaults to the `repository_name` setting.
+    #[clap(long)]
+    repository_name: Option<String>,
+    /// The release channel to list, and which a "latest" version requirement resolves against.
+    ///
+    /// "stable" only considers stable releases, "prerelease" also considers prereleases,
+    /// and "all" also considers draft releases. Defaults to the `channel` setting.
+    #[clap(long, value_enum)]
+    channel: Option<ReleaseChannel>,
 }
 
 #[derive(Debug, Clone, clap::Parser)]
@@ -44,31 +71,82 @@ pub struct DeleteCmd {
     /// The cached installation to delete.
     #[clap(value_parser = value_parser!(RepoOrVersion))]
     version: RepoOrVersion,
+    /// The GitHub repository the version to delete was installed from.
+    ///
+    /// Defaults to the `repository_name` setting.
+    #[clap(long)]
+    repository_name: Option<String>,
+}
+
+/// The output of `penv cache list`.
+#[derive(Debug, Serialize)]
+struct InstalledReleases<'a> {
+    installed_releases: Vec<&'a InstalledRelease>,
+}
+
+/// The output of `penv cache available`.
+#[derive(Debug, Serialize)]
+struct AvailableReleases {
+    repository_name: String,
+    releases: Vec<AvailableRelease>,
+}
+
+#[derive(Debug, Serialize)]
+struct AvailableRelease {
+    #[serde(flatten)]
+    release: Release,
+    /// Whether the release is installed in the cache.
+    installed: bool,
 }
 
 impl CacheCmd {
-    pub async fn exec(&self, home: Utf8PathBuf) -> Result<()> {
+    pub async fn exec(&self, home: Utf8PathBuf, output: OutputFormat) -> Result<()> {
         match self {
             CacheCmd {
-                subcmd: CacheTopSubCmd::List(ListCmd { required_version }),
+                subcmd:
+                    CacheTopSubCmd::List(ListCmd {
+                        required_version,
+                        repository_name,
+                        channel,
+                    }),
             } => {
+                let config = Config::load(&home)?;
                 let cache = crate::penv::cache::cache::Cache::new(home)?;
-                let versions = cache.list_installed(required_version.as_ref())?;
-                for version in versions {
-                    println!("{}", version);
-                }
-                Ok(())
+                let versions = cache.list_installed(
+                    repository_name.as_deref(),
+                    required_version.as_ref(),
+                    channel.unwrap_or_else(|| config.channel()),
+                )?;
+                let installed = InstalledReleases {
+                    installed_releases: versions,
+                };
+                output.print(&installed, || {
+                    for version in &installed.installed_releases {
+                        match version {
+                            InstalledRelease::Binary(release)
+                                if release.repository_name != config.repository_name() =>
+                            {
+                                println!("{} ({})", release, release.repository_name)
+                            }
+                            _ => println!("{}", version),
+                        }
+                    }
+                    Ok(())
+                })
             }
             CacheCmd {
-                subcmd: CacheTopSubCmd::Delete(DeleteCmd { version }),
+                subcmd:
+                    CacheTopSubCmd::Delete(DeleteCmd {
+                        version,
+                        repository_name,
+                    }),
             } => {
                 // don't allow deletion if environment uses this version
-                let mut penv = crate::penv::Penv::new(home.clone())?;
-                if let Some(env) = penv
-                    .environments
-                    .iter()
-                    .find(|e| (**e).satisfied_by_version(version))
-                {
+                let mut penv = Penv::new(home.clone())?;
+                let repository_name = repository_name
+                    .clone()
+                    .unwrap_or_else(|| penv.config.repository_name());
+                if let Some(env) = environment_using(&penv, &repository_name, version) {
                     return Err(anyhow::anyhow!(
                         "Cannot delete version {} because it is pinned by environment {}",
                         version,
@@ -76,7 +154,7 @@ impl CacheCmd {
                     ));
                 }
 
-                let installed_version = penv.cache.get_installed_release(version);
+                let installed_version = penv.cache.get_installed_release(&repository_name, version);
 
                 match installed_version {
                     Some(installed_version) => {
@@ -85,22 +163,53 @@ impl CacheCmd {
                         penv.cache.persist()?;
                         Ok(())
                     }
-                    None => Err(anyhow!("Version {} is not installed", version)),
+                    None => Err(anyhow!(
+                        "Version {} from {} is not installed",
+                        version,
+                        repository_name
+                    )),
                 }
             }
             CacheCmd {
-                subcmd: CacheTopSubCmd::Available(AvailableCmd { required_version }),
+                subcmd:
+                    CacheTopSubCmd::Available(AvailableCmd {
+                        required_version,
+                        repository_name,
+                        channel,
+                    }),
             } => {
-                let penv = crate::penv::Penv::new(home.clone())?;
-                let releases = penv.list_available(required_version.as_ref()).await?;
-                for (release, installed) in releases {
-                    if installed {
-                        println!("{}", release.version.to_string().green());
-                    } else {
-                        println!("{}", release.version.to_string().red());
+                let penv = Penv::new(home.clone())?;
+                let repository_name = repository_name
+                    .clone()
+                    .unwrap_or_else(|| penv.config.repository_name());
+                let channel = channel.unwrap_or_else(|| penv.config.channel());
+                let releases = penv
+                    .list_available(&repository_name, required_version.as_ref(), channel)
+                    .await?;
+                let available = AvailableReleases {
+                    repository_name,
+                    releases: releases
+                        .into_iter()
+                        .map(|(release, installed)| AvailableRelease { release, installed })
+                        .collect(),
+                };
+                output.print(&available, || {
+                    for AvailableRelease { release, installed } in &available.releases {
+                        let mut line = release.version.to_string();
+                        if release.draft {
+                            line.push_str(" (draft)");
+                        } else if release.is_prerelease() {
+                            line.push_str(" (prerelease)");
+                        }
+
+                        if *installed {
+                            println!("{}", line.green());
+                        } else {
+                            println!("{}", line.red());
+                        }
                     }
-                }
-                Ok(())
+                    Ok(())
+                })
             }
             CacheCmd {
                 subcmd: CacheTopSubCmd::Reset,
@@ -120,3 +229,113 @@ impl CacheCmd {
         }
     }
 }
+
+/// Returns an environment that uses the given release: one pinned to it, or one whose
+/// version requirement it satisfies.
+fn environment_using<'a>(
+    penv: &'a Penv,
+    repository_name: &str,
+    version: &RepoOrVersion,
+) -> Option<&'a Arc<Environment>> {
+    penv.environments.iter().find(|e| match ***e {
+        Environment::BinaryEnvironment(ref env) => {
+            env.repository_name == repository_name
+                && (matches!(version, RepoOrVersion::Version(v) if v == &env.pinned_version)
+                    || e.satisfied_by_version(version))
+        }
+        Environment::CheckoutEnvironment(_) => e.satisfied_by_version(version),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr as _;
+
+    use semver::Version;
+    use serde_json::json;
+    use target_lexicon::Triple;
+
+    use super::*;
+    use crate::penv::release::Asset;
+
+    #[test]
+    fn available_output_layout() {
+        let available = AvailableReleases {
+            repository_name: "penumbra-zone/penumbra".to_string(),
+            releases: vec![AvailableRelease {
+                release: Release {
+                    version: Version::new(1, 0, 0),
+                    body: None,
+                    assets: vec![Asset {
+                        target_arch: Some(Triple::from_str("x86_64-unknown-linux-gnu").unwrap()),
+                        browser_download_url: "https://example.com/pcli.tar.gz".to_string(),
+                        expected_sha256sum: None,
+                    }],
+                    name: "v1.0.0".to_string(),
+                    prerelease: false,
+                    draft: false,
+                },
+                installed: true,
+            }],
+        };
+
+        assert_eq!(
+            serde_json::to_value(&available).unwrap(),
+            json!({
+                "repository_name": "penumbra-zone/penumbra",
+                "releases": [{
+                    "version": "1.0.0",
+                    "body": null,
+                    "assets": [{
+                        "target_arch": "x86_64-unknown-linux-gnu",
+                        "browser_download_url": "https://example.com/pcli.tar.gz",
+                        "expected_sha256sum": null,
+                    }],
+                    "name": "v1.0.0",
+                    "prerelease": false,
+                    "draft": false,
+                    "installed": true,
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn pinned_version_is_in_use() {
+        use crate::penv::penv::tests::test_penv;
+        use crate::penv::release::VersionReqOrLatest;
+
+        let home = tempfile::tempdir().unwrap();
+        let home = camino::Utf8Path::from_path(home.path()).unwrap();
+        let mut penv = test_penv(home);
+        let version = |v: &str| RepoOrVersion::Version(Version::parse(v).unwrap());
+
+        // An environment pinned outside its requirement still uses its pinned version.
+        let mut environment = penv
+            .environments
+            .get_environment("binary")
+            .unwrap()
+            .as_ref()
+            .clone();
+        if let Environment::BinaryEnvironment(ref mut env) = environment {
+            env.version_requirement = VersionReqOrLatest::VersionReq("^2".parse().unwrap());
+        }
+        penv.environments.retain(|e| e.metadata().alias != "binary");
+        penv.environments.push(Arc::new(environment));
+
+        let in_use = |penv: &Penv, repository_name: &str, v: &str| {
+            environment_using(penv, repository_name, &version(v))
+                .map(|e| e.metadata().alias.clone())
+        };
+        assert_eq!(
+            in_use(&penv, "test/test", "1.0.0").as_deref(),
+            Some("binary")
+        );
+        assert_eq!(
+            in_use(&penv, "test/test", "2.1.0").as_deref(),
+            Some("binary")
+        );
+        assert_eq!(in_use(&penv, "test/test", "1.1.0"), None);
+        assert_eq!(in_use(&penv, "fork/penumbra", "1.0.0"), None);
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::BTreeSet;
+use std::{env, process};
+
+use anyhow::{anyhow, Result};
+use camino::Utf8PathBuf;
+use clap::CommandFactory as _;
+
+use crate::opt::Opt;
+use crate::penv::environment::EnvironmentTrait as _;
+use crate::penv::release::InstalledRelease;
+use crate::penv::snapshot::published_versions;
+use crate::penv::Penv;
+
+use super::hook::Shell;
+
+#[derive(Debug, clap::Parser)]
+pub struct CompletionsCmd {
+    /// The shell to output completions for. Defaults to the `shell` setting.
+    #[clap(value_enum)]
+    shell: Option<Shell>,
+    /// Print the completion candidates for the word following `words`, one per line,
+    /// exiting non-zero if the candidates don't depend on penv's state.
+    ///
+    /// Used by the generated completion scripts.
+    #[clap(long, hide = true)]
+    candidates: bool,
+    /// The words on the command line before the one being completed, excluding `penv`.
+    #[clap(last = true, hide = true)]
+    words: Vec<String>,
+}
+
+/// The kinds of arguments whose completions depend on penv's state.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Candidates {
+    /// The aliases of all environments.
+    Environments,
+    /// The installed versions.
+    Versions,
+    /// The installed versions and git checkouts.
+    Installed,
+    /// Version requirements to install, from the installed versions and the versions
+    /// last fetched from the repositories.
+    VersionRequirements,
+}
+
+impl CompletionsCmd {
+    pub async fn exec(&self, home: Utf8PathBuf) -> Result<()> {
+        if self.candidates {
+            match candidates_for(&self.words) {
+                Some(candidates) => return print_candidates(home, candidates),
+                None => process::exit(1),
+            }
+        }
+
+        let (shell, template) = match Shell::or_configured(&self.shell, &home)? {
+            Shell::Bash => (
+                clap_complete::Shell::Bash,
+                include_str!("../../files/bash-completions.j2"),
+            ),
+            Shell::Zsh => (
+                clap_complete::Shell::Zsh,
+                include_str!("../../files/zsh-completions.j2"),
+            ),
+            Shell::Fish => (
+                clap_complete::Shell::Fish,
+                include_str!("../../files/fish-completions.j2"),
+            ),
+            _ => {
+                return Err(anyhow!(
+                    "please provide a supported shell: `zsh`, `bash` or `fish`"
+                ))
+            }
+        };
+
+        let mut completions = Vec::new();
+        clap_complete::generate(shell, &mut Opt::command(), "penv", &mut completions);
+        let mut completions = String::from_utf8(completions)?;
+        // The generated zsh script ends by running the completion function, which the
+        // dynamic completions take over.
+        if let Some(stripped) = completions.trim_end().strip_suffix("_penv \"$@\"") {
+            completions = stripped.to_string();
+        }
+
+        let current_exe =
+            env::current_exe().map_err(|e| anyhow!("failed to get current exe path: {e}"))?;
+        let mut context = tera::Context::new();
+        context.insert("penv_executable", &current_exe);
+        let dynamic_completions = tera::Tera::one_off(template, &context, false)?;
+
+        print!("{}", completions);
+        println!("{}", dynamic_completions);
+
+        Ok(())
+    }
+}
+
+/// Determines which candidates complete the word following `words`, by following
+/// them through penv's subcommands.
+fn candidates_for(words: &[String]) -> Option<Candidates> {
+    let mut command = Opt::command();
+    // Options of parent commands stay valid within subcommands.
+    let mut value_options = value_options(&command);
+    let mut path = Vec::new();
+    let mut positionals = 0;
+    let mut pending_option = None;
+
+    for word in words {
+        if pending_option.take().is_some() {
+            continue;
+        }
+
+        if let Some(long) = word.strip_prefix("--") {
+            if !long.contains('=') {
+                pending_option = value_options
+                    .iter()
+                    .find(|(_, option_long, _)| *option_long == Some(long))
+                    .map(|(id, _, _)| *id);
+            }
+        } else if let Some(short) = word.strip_prefix('-') {
+            let mut chars = short.chars();
+            if let (Some(short), None) = (chars.next(), chars.next()) {
+                pending_option = value_options
+                    .iter()
+                    .find(|(_, _, option_short)| *option_short == Some(short))
+                    .map(|(id, _, _)| *id);
+            }
+        } else if let Some(subcommand) = command
+            .find_subcommand(word.as_str())
+            .filter(|_| positionals == 0)
+        {
+            path.push(subcommand.get_name().to_string());
+            command = subcommand.clone();
+            value_options.extend(self::value_options(&command));
+        } else {
+            positionals += 1;
+        }
+    }
+
+    if let Some(option) = pending_option {
+        return match option {
+            "penumbra-version" => Some(Candidates::VersionRequirements),
+            _ => None,
+        };
+    }
+
+    let path = path.iter().map(String::as_str).collect::<Vec<_>>();
+    match (path.as_slice(), positionals) {
+        (["use" | "shell" | "exec"], 0) => Some(Candidates::Environments),
+        // Every other `manage` subcommand takes an existing environment
+        (["manage", "create" | "list" | "help"], _) => None,
+        (["manage", _], 0) => Some(Candidates::Environments),
+        (["manage", "set"], 1) => Some(Candidates::Versions),
+        (["cache", "delete"], 0) => Some(Candidates::Installed),
+        (["install"], 0) => Some(Candidates::VersionRequirements),
+        _ => None,
+    }
+}
+
+/// The ids, long and short names of a command's options that take a value.
+fn value_options(
+    command: &clap::Command<'static>,
+) -> Vec<(&'static str, Option<&'static str>, Option<char>)> {
+    command
+        .get_arguments()
+        .filter(|arg| arg.is_takes_value_set() && !arg.is_positional())
+        .map(|arg| (arg.get_id(), arg.get_long(), arg.get_short()))
+        .collect()
+}
+
+fn print_candidates(home: Utf8PathBuf, candidates: Candidates) -> Result<()> {
+    let penv = Penv::new(home)?;
+
+    if candidates == Candidates::Environments {
+        for environment in penv.environments.iter() {
+            println!("{}", environment.metadata().alias);
+        }
+        return Ok(());
+    }
+
+    // The same version can be installed from several repositories.
+    let mut versions = BTreeSet::new();
+    let mut checkouts = Vec::new();
+    for installed_release in &penv.cache.data.installed_releases {
+        match installed_release {
+            InstalledRelease::Binary(release) => {
+                versions.insert(release.version.clone());
+            }
+            InstalledRelease::GitCheckout(checkout) => checkouts.push(&checkout.url),
+        }
+    }
+
+    if candidates == Candidates::VersionRequirements {
+        println!("latest");
+        versions.extend(published_versions(&penv.home_dir, penv.config.channel()));
+    }
+    for version in versions.iter().rev() {
+        println!("{}", version);
+    }
+    if candidates == Candidates::Installed {
+        for url in checkouts {
+            println!("{}", url);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{candidates_for, Candidates};
+
+    #[test]
+    fn dynamic_candidates() {
+        let candidates = |line: &str| {
+            candidates_for(
+                &line
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        assert_eq!(candidates("use"), Some(Candidates::Environments));
+        assert_eq!(
+            candidates("--home /tmp/penv u"),
+            Some(Candidates::Environments)
+        );
+        assert_eq!(candidates("use testnet"), None);
+        assert_eq!(
+            candidates("manage upgrade --fetch"),
+            Some(Candidates::Environments)
+        );
+        assert_eq!(candidates("manage set testnet"), Some(Candidates::Versions));
+        assert_eq!(candidates("manage create"), None);
+        assert_eq!(
+            candidates("manage create devnet --penumbra-version"),
+            Some(Candidates::VersionRequirements)
+        );
+        assert_eq!(candidates("manage create devnet --grpc-url"), None);
+        assert_eq!(candidates("cache delete"), Some(Candidates::Installed));
+        assert_eq!(
+            candidates("install --repository-name penumbra-zone/penumbra"),
+            Some(Candidates::VersionRequirements)
+        );
+        assert_eq!(candidates(""), None);
+    }
+}
@@ -0,0 +1,150 @@
+use std::env;
+
+use anyhow::{anyhow, Result};
+use camino::Utf8PathBuf;
+use clap::ValueEnum as _;
+use serde::Serialize;
+
+use super::OutputFormat;
+use crate::penv::config::{Config, ConfigKey, ConfigSource};
+
+#[derive(Debug, clap::Parser)]
+pub struct ConfigCmd {
+    #[clap(subcommand)]
+    pub subcmd: ConfigTopSubCmd,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum ConfigTopSubCmd {
+    /// Print the effective value of a setting.
+    #[clap(display_order = 100)]
+    Get(GetCmd),
+    /// Set a setting in the config file.
+    #[clap(display_order = 200)]
+    Set(SetCmd),
+    /// Remove a setting from the config file, falling back to its default.
+    #[clap(display_order = 300)]
+    Unset(UnsetCmd),
+    /// List every setting, with its effective value and where it comes from.
+    #[clap(display_order = 400)]
+    List(ListCmd),
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+pub struct GetCmd {
+    /// The setting to print.
+    #[clap(value_enum)]
+    key: ConfigKey,
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+pub struct SetCmd {
+    /// The setting to set.
+    #[clap(value_enum)]
+    key: ConfigKey,
+    /// The value to set it to.
+    value: String,
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+pub struct UnsetCmd {
+    /// The setting to remove.
+    ///
+    /// Settings penv doesn't know, for example misspelt ones, can be removed as well.
+    key: String,
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+pub struct ListCmd {
+    /// Show the values of secrets, such as the GitHub token, instead of hiding them.
+    #[clap(long)]
+    show_secrets: bool,
+}
+
+/// The output of `penv config list`.
+#[derive(Debug, Serialize)]
+struct SettingList {
+    settings: Vec<Setting>,
+}
+
+#[derive(Debug, Serialize)]
+struct Setting {
+    name: &'static str,
+    /// The effective value, which is omitted if the setting is unset or a hidden secret.
+    value: Option<String>,
+    /// Where the value comes from, omitted if the setting is unset.
+    source: Option<ConfigSource>,
+}
+
+impl ConfigCmd {
+    pub async fn exec(&self, home: Utf8PathBuf, output: OutputFormat) -> Result<()> {
+        match &self.subcmd {
+            ConfigTopSubCmd::Get(GetCmd { key }) => match Config::load(&home)?.get(*key)? {
+                Some((value, _source)) => println!("{}", value),
+                None => return Err(anyhow!("{} is not set", key)),
+            },
+            // Changing the file doesn't load the configuration, so that an invalid setting
+            // in the file or the environment can be fixed.
+            ConfigTopSubCmd::Set(SetCmd { key, value }) => {
+                Config::set(&home, *key, Some(value))?;
+                warn_if_overridden(*key);
+            }
+            ConfigTopSubCmd::Unset(UnsetCmd { key }) => {
+                let removed = Config::unset(&home, key)?;
+                match ConfigKey::from_str(key, false) {
+                    Ok(key) => warn_if_overridden(key),
+                    Err(_) if !removed => return Err(anyhow!("{} is not a setting", key)),
+                    Err(_) => {}
+                }
+            }
+            ConfigTopSubCmd::List(ListCmd { show_secrets }) => {
+                let config = Config::load(&home)?;
+                let mut list = SettingList {
+                    settings: Vec::new(),
+                };
+                for key in ConfigKey::ALL {
+                    let (value, source) = match config.get(key)? {
+                        Some((value, source)) => (Some(value), Some(source)),
+                        None => (None, None),
+                    };
+                    list.settings.push(Setting {
+                        name: key.name(),
+                        value: value.filter(|_| !key.is_secret() || *show_secrets),
+                        source,
+                    });
+                }
+
+                output.print(&list, || {
+                    println!("{:<18} {:<32} SOURCE", "SETTING", "VALUE");
+                    for (key, setting) in ConfigKey::ALL.iter().zip(&list.settings) {
+                        let value = match (&setting.value, setting.source) {
+                            (Some(value), _) => value.clone(),
+                            (None, Some(_)) => "(hidden)".to_string(),
+                            (None, None) => "-".to_string(),
+                        };
+                        let source = match setting.source {
+                            Some(ConfigSource::Environment) => key.env_var(),
+                            Some(source) => source.to_string(),
+                            None => "unset".to_string(),
+                        };
+                        println!("{:<18} {:<32} {}", key, value, source);
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Points out that the environment variable for `key` takes precedence over the config
+/// file, so that the change has no effect while it's set.
+fn warn_if_overridden(key: ConfigKey) {
+    if env::var_os(key.env_var()).is_some() {
+        eprintln!(
+            "{} is set in the environment and takes precedence over the config file",
+            key.env_var()
+        );
+    }
+}
@@ -0,0 +1,505 @@
+use std::{env, fs};
+
+use anyhow::{anyhow, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use colored::Colorize;
+
+use crate::penv::environment::{
+    replace_symlinks, Environment, EnvironmentTrait as _, ManagedFile as _,
+};
+use crate::penv::project::{current_dir, EnvironmentSource};
+use crate::penv::release::{InstalledRelease, RepoOrVersion, UsableRelease as _};
+use crate::penv::Penv;
+
+#[derive(Debug, clap::Parser)]
+pub struct DoctorCmd {
+    /// Apply the safe repairs for the problems found, and report what they changed.
+    ///
+    /// Releases and environments whose files are gone are forgotten, and symlinks are
+    /// pointed at the pinned versions. Problems with the shell setup are only reported.
+    #[clap(long)]
+    pub fix: bool,
+}
+
+/// A problem found with the penv home or the shell it's used in.
+struct Problem {
+    description: String,
+    /// The repair for the problem, if it can be repaired safely.
+    repair: Option<Repair>,
+}
+
+enum Repair {
+    /// Update the environments after the home was moved, as `penv relocate` does.
+    Relocate,
+    /// Forget an installed release whose files are missing.
+    ForgetRelease(InstalledRelease),
+    /// Forget an environment whose directory is gone.
+    ForgetEnvironment(String),
+    /// Point the symlinks of an environment at its pinned version.
+    Relink(String),
+    /// Point the global `bin` symlink at the active environment, or remove it if no
+    /// environment is active.
+    RelinkActive,
+}
+
+impl DoctorCmd {
+    pub async fn exec(&self, home: Utf8PathBuf) -> Result<()> {
+        let mut penv = Penv::new(home.clone())?;
+
+        let problems = diagnose(&penv);
+        if problems.is_empty() {
+            println!("{}", "no problems found".green());
+            return Ok(());
+        }
+
+        let mut remaining = 0;
+        for problem in &problems {
+            println!("{}", problem.description.red());
+            match (&problem.repair, self.fix) {
+                (Some(repair), true) => match repair.apply(&mut penv) {
+                    Ok(change) => println!("  {} {}", "fixed:".green(), change),
+                    Err(e) => {
+                        remaining += 1;
+                        println!("  failed to fix: {:#}", e);
+                    }
+                },
+                (Some(_), false) => {
+                    remaining += 1;
+                    println!("  can be fixed with `penv doctor --fix`");
+                }
+                (None, _) => remaining += 1,
+            }
+        }
+
+        if self.fix {
+            penv.persist()?;
+        }
+        if remaining > 0 {
+            return Err(anyhow!(
+                "found {} problem(s) that need attention in {}",
+                remaining,
+                home
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Repair {
+    /// Applies the repair, returning a description of what changed.
+    fn apply(&self, penv: &mut Penv) -> Result<String> {
+        match self {
+            Repair::Relocate => {
+                let previous_home = penv.previous_home.clone().unwrap_or_default();
+                penv.finish_relocation()?;
+                Ok(format!(
+                    "updated the environments from {} to {}",
+                    previous_home, penv.home_dir
+                ))
+            }
+            Repair::ForgetRelease(release) => {
+                penv.cache.data.installed_releases.retain(|r| r != release);
+                Ok(format!("removed {} from cache.toml", release))
+            }
+            Repair::ForgetEnvironment(alias) => {
+                penv.environments.retain(|e| &e.metadata().alias != alias);
+                if penv
+                    .active_environment
+                    .as_ref()
+                    .is_some_and(|e| &e.metadata().alias == alias)
+                {
+                    penv.active_environment = None;
+                }
+                Ok(format!("removed environment {} from penv.toml", alias))
+            }
+            Repair::Relink(alias) => {
+                let environment = penv
+                    .environments
+                    .get_environment(alias)
+                    .ok_or_else(|| anyhow!("Environment with alias {} does not exist", alias))?;
+                match environment.as_ref() {
+                    Environment::BinaryEnvironment(env) => {
+                        env.switch_symlinks(&penv.cache)?;
+                        Ok(format!(
+                            "pointed the symlinks of environment {} at version {}",
+                            alias, env.pinned_version
+                        ))
+                    }
+                    Environment::CheckoutEnvironment(_) => Err(anyhow!(
+                        "environment {} is a git checkout environment, which has no symlinks",
+                        alias
+                    )),
+                }
+            }
+            Repair::RelinkActive => {
+                let link = penv.home_dir.join("bin");
+                match penv.active_environment.clone() {
+                    Some(environment) if environment.bin_dir().is_dir() => {
+                        replace_symlinks(&[(environment.bin_dir(), link.clone())])?;
+                        Ok(format!(
+                            "pointed {} at environment {}",
+                            link,
+                            environment.metadata().alias
+                        ))
+                    }
+                    _ => {
+                        penv.deactivate()?;
+                        Ok(format!("removed {}", link))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Checks the penv home and the current shell's setup, returning the problems found.
+fn diagnose(penv: &Penv) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    // Moving the home leaves all symlinks pointing at the previous home, and relocating
+    // recreates them, so they're only checked once the home is up to date.
+    let relocated = penv.previous_home.is_some();
+    if let Some(previous_home) = &penv.previous_home {
+        problems.push(Problem {
+            description: format!(
+                "the penv home was moved from {} to {} without `penv relocate`",
+                previous_home, penv.home_dir
+            ),
+            repair: Some(Repair::Relocate),
+        });
+    }
+
+    let mut broken_releases = Vec::new();
+    for release in &penv.cache.data.installed_releases {
+        let missing = missing_files(release);
+        if !missing.is_empty() {
+            problems.push(Problem {
+                description: format!(
+                    "cache.toml: {} of installed release {} does not exist",
+                    missing.join(", "),
+                    release
+                ),
+                repair: Some(Repair::ForgetRelease(release.clone())),
+            });
+            broken_releases.push(release);
+        }
+    }
+
+    for environment in penv.environments.iter() {
+        let alias = &environment.metadata().alias;
+        if !environment.path().is_dir() {
+            problems.push(Problem {
+                description: format!(
+                    "penv.toml: the directory {} of environment {} does not exist",
+                    environment.path(),
+                    alias
+                ),
+                repair: Some(Repair::ForgetEnvironment(alias.clone())),
+            });
+            continue;
+        }
+
+        match environment.as_ref() {
+            Environment::BinaryEnvironment(env) => {
+                let pinned = RepoOrVersion::Version(env.pinned_version.clone());
+                let installed = penv
+                    .cache
+                    .get_installed_release(&env.repository_name, &pinned)
+                    .filter(|release| !broken_releases.contains(release));
+                if installed.is_none() {
+                    problems.push(Problem {
+                        description: format!(
+                            "environment {} is pinned to {} from {}, which is not installed; reinstall it with `penv install --repository-name {} {}`",
+                            alias, env.pinned_version, env.repository_name, env.repository_name, env.pinned_version
+                        ),
+                        repair: None,
+                    });
+                    continue;
+                }
+                if relocated {
+                    continue;
+                }
+
+                let stale_links = match env.symlink_targets(&penv.cache) {
+                    Ok(targets) => targets
+                        .into_iter()
+                        .filter_map(|(target, link)| stale_link(&link, &target))
+                        .collect::<Vec<_>>(),
+                    Err(e) => vec![format!("{:#}", e)],
+                };
+                if !stale_links.is_empty() {
+                    problems.push(Problem {
+                        description: format!(
+                            "the symlinks of environment {} don't point at its pinned version {}: {}",
+                            alias,
+                            env.pinned_version,
+                            stale_links.join(", ")
+                        ),
+                        repair: Some(Repair::Relink(alias.clone())),
+                    });
+                }
+            }
+            Environment::CheckoutEnvironment(env) => {
+                if !env.checkout_dir().is_dir() {
+                    problems.push(Problem {
+                        description: format!(
+                            "the git checkout {} of environment {} does not exist",
+                            env.checkout_dir(),
+                            alias
+                        ),
+                        repair: None,
+                    });
+                }
+            }
+        }
+    }
+
+    if !relocated {
+        diagnose_active_link(penv, &mut problems);
+    }
+    diagnose_shell(penv, &mut problems);
+
+    problems
+}
+
+/// Checks that the global `bin` symlink points at the active environment.
+fn diagnose_active_link(penv: &Penv, problems: &mut Vec<Problem>) {
+    let link = penv.home_dir.join("bin");
+    let description = match &penv.active_environment {
+        Some(environment) => stale_link(&link, &environment.bin_dir()).map(|problem| {
+            format!(
+                "{}, which should point at the active environment {}",
+                problem,
+                environment.metadata().alias
+            )
+        }),
+        None => match read_link(&link) {
+            Some(target) if !target.exists() => {
+                Some(format!("{} is a dangling symlink to {}", link, target))
+            }
+            Some(target) => Some(format!(
+                "{} points at {}, but no environment is active",
+                link, target
+            )),
+            None => None,
+        },
+    };
+
+    if let Some(description) = description {
+        problems.push(Problem {
+            description,
+            repair: Some(Repair::RelinkActive),
+        });
+    }
+}
+
+/// Checks that the shell hook is loaded, and that nothing on the `PATH` shadows the
+/// binaries of the effective environment.
+fn diagnose_shell(penv: &Penv, problems: &mut Vec<Problem>) {
+    // Without an effective environment, the hook doesn't change the shell, so there's
+    // no telling whether it's loaded.
+    let (environment, source) = match current_dir().and_then(|dir| penv.effective_environment(&dir))
+    {
+        Ok(Some(effective_environment)) => effective_environment,
+        Ok(None) => return,
+        Err(e) => {
+            problems.push(Problem {
+                description: format!("{:#}", e),
+                repair: None,
+            });
+            return;
+        }
+    };
+    let alias = &environment.metadata().alias;
+
+    if env::var_os("PENV_PATH_ADDED").is_none() {
+        problems.push(Problem {
+            description: format!(
+                "the shell hook is not loaded in this shell, so environment {} is not on the PATH; add the output of `penv hook` to your shell's startup file, as described in the README",
+                alias
+            ),
+            repair: None,
+        });
+        return;
+    }
+
+    let bin_dir = match source {
+        EnvironmentSource::Global => penv.home_dir.join("bin"),
+        _ => environment.bin_dir(),
+    };
+    let path = env::var_os("PATH").unwrap_or_default();
+    let path_dirs = env::split_paths(&path)
+        .filter_map(|dir| Utf8PathBuf::from_path_buf(dir).ok())
+        .collect::<Vec<_>>();
+
+    let mut binaries = vec!["pcli", "pclientd"];
+    if !environment.metadata().client_only {
+        binaries.push("pd");
+    }
+    for binary in binaries {
+        let first = path_dirs.iter().find(|dir| dir.join(binary).is_file());
+        match first {
+            Some(dir) if dir != &bin_dir => problems.push(Problem {
+                description: format!(
+                    "{} shadows the {} of environment {}; put {} before {} on the PATH",
+                    dir.join(binary),
+                    binary,
+                    alias,
+                    bin_dir,
+                    dir
+                ),
+                repair: None,
+            }),
+            Some(_) => {}
+            None if !path_dirs.contains(&bin_dir) => problems.push(Problem {
+                description: format!("{} of environment {} is not on the PATH", bin_dir, alias),
+                repair: None,
+            }),
+            None => {}
+        }
+    }
+}
+
+/// The files of an installed release that don't exist.
+fn missing_files(release: &InstalledRelease) -> Vec<String> {
+    match release {
+        InstalledRelease::Binary(_) => release
+            .assets()
+            .iter()
+            .map(|asset| &asset.local_filepath)
+            .filter(|path| !path.is_file())
+            .map(|path| path.to_string())
+            .collect(),
+        InstalledRelease::GitCheckout(checkout) => Some(&checkout.install_path)
+            .filter(|path| !path.is_dir())
+            .map(|path| path.to_string())
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// Describes how the symlink `link` differs from pointing at `target`, if it does.
+fn stale_link(link: &Utf8Path, target: &Utf8Path) -> Option<String> {
+    match read_link(link) {
+        Some(current) if current == target && target.exists() => None,
+        Some(current) if !current.exists() => {
+            Some(format!("{} is a dangling symlink to {}", link, current))
+        }
+        Some(current) => Some(format!("{} points at {}", link, current)),
+        None if fs::symlink_metadata(link).is_ok() => Some(format!("{} is not a symlink", link)),
+        None => Some(format!("{} is missing", link)),
+    }
+}
+
+fn read_link(link: &Utf8Path) -> Option<Utf8PathBuf> {
+    fs::read_link(link)
+        .ok()
+        .and_then(|target| Utf8PathBuf::from_path_buf(target).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn stale_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let pinned = dir.join("1.0.0");
+        let pruned = dir.join("0.9.0");
+        fs::write(&pinned, "").unwrap();
+
+        let link = dir.join("pcli");
+        assert!(stale_link(&link, &pinned).unwrap().ends_with("is missing"));
+
+        std::os::unix::fs::symlink(&pruned, &link).unwrap();
+        assert!(stale_link(&link, &pinned)
+            .unwrap()
+            .contains("is a dangling symlink"));
+
+        fs::remove_file(&link).unwrap();
+        std::os::unix::fs::symlink(&pinned, &link).unwrap();
+        assert_eq!(stale_link(&link, &pinned), None);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn repair_broken_home() {
+        use crate::penv::penv::tests::test_penv;
+        use crate::penv::state::StateFile;
+
+        let home = tempfile::tempdir().unwrap();
+        let home = Utf8Path::from_path(home.path()).unwrap();
+        let mut penv = test_penv(home);
+
+        // An installed release whose binaries were deleted.
+        let mut pruned = penv.cache.data.installed_releases[0].clone();
+        if let InstalledRelease::Binary(release) = &mut pruned {
+            release.version = semver::Version::new(0, 9, 0);
+            release.name = "0.9.0".into();
+            release.root_dir = home.join("versions/0.9.0");
+            for asset in &mut release.assets {
+                asset.local_filepath = release
+                    .root_dir
+                    .join("bin")
+                    .join(asset.local_filepath.file_name().unwrap());
+            }
+        }
+        penv.cache.data.installed_releases.push(pruned.clone());
+        penv.cache.persist().unwrap();
+
+        // An environment link to the pruned version.
+        let pd_link = home.join("environments/binary/bin/pd");
+        fs::remove_file(&pd_link).unwrap();
+        std::os::unix::fs::symlink(home.join("versions/0.9.0/bin/pd"), &pd_link).unwrap();
+        // An environment whose directory is gone.
+        fs::remove_dir_all(home.join("environments/checkout")).unwrap();
+        // A global bin link to it, although no environment is active.
+        std::os::unix::fs::symlink(home.join("environments/checkout/bin"), home.join("bin"))
+            .unwrap();
+
+        let mut penv = Penv::new(home.to_owned()).unwrap();
+        let problems = diagnose(&penv);
+        let repairs = problems
+            .iter()
+            .map(|problem| problem.repair.as_ref().expect(&problem.description))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            repairs.len(),
+            4,
+            "{:?}",
+            problems.iter().map(|p| &p.description).collect::<Vec<_>>()
+        );
+        assert!(matches!(repairs[0], Repair::ForgetRelease(release) if release == &pruned));
+        assert!(matches!(repairs[1], Repair::Relink(alias) if alias == "binary"));
+        assert!(matches!(repairs[2], Repair::ForgetEnvironment(alias) if alias == "checkout"));
+        assert!(matches!(repairs[3], Repair::RelinkActive));
+
+        for problem in &problems {
+            problem.repair.as_ref().unwrap().apply(&mut penv).unwrap();
+        }
+        penv.persist().unwrap();
+
+        assert_eq!(
+            fs::read_link(&pd_link).unwrap(),
+            home.join("versions/1.0.0/bin/pd")
+        );
+        assert!(fs::symlink_metadata(home.join("bin")).is_err());
+
+        let penv_file = StateFile::Penv.read(home).unwrap().unwrap();
+        let environments = penv_file.table["environments"]["environments"]
+            .as_array()
+            .unwrap();
+        assert_eq!(environments.len(), 1);
+        assert!(environments[0].to_string().contains("\"binary\""));
+        let cache_file = StateFile::Cache.read(home).unwrap().unwrap();
+        let releases = cache_file.table["installed_releases"].as_array().unwrap();
+        assert_eq!(releases.len(), 1);
+        assert!(releases[0].to_string().contains("\"1.0.0\""));
+
+        let penv = Penv::new(home.to_owned()).unwrap();
+        assert!(diagnose(&penv).is_empty());
+    }
+}
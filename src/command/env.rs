@@ -1,75 +1,198 @@
 use anyhow::{anyhow, Result};
 use camino::Utf8PathBuf;
 
-use crate::penv::environment::EnvironmentTrait as _;
+use crate::penv::environment::{EnvironmentTrait as _, ENVIRONMENT_VARIABLE_NAMES};
+use crate::penv::project::{current_dir, EnvironmentSource};
+use crate::penv::snapshot::{state_key, Snapshot};
 use crate::penv::Penv;
 
 use super::hook::Shell;
 
 #[derive(Debug, clap::Parser)]
 pub struct EnvCmd {
-    /// Which shell environment to print configuration for.
-    #[clap(default_value_t, value_enum)]
-    shell: Shell,
+    /// Which shell environment to print configuration for. Defaults to the `shell` setting.
+    #[clap(value_enum)]
+    shell: Option<Shell>,
+    /// Print nothing if the configuration is unchanged since the output with this key
+    /// was printed.
+    ///
+    /// Used by the shell hooks, which run on every prompt.
+    #[clap(long, value_name = "KEY")]
+    if_changed: Option<String>,
 }
 
 impl EnvCmd {
     pub async fn exec(&self, home: Utf8PathBuf) -> Result<()> {
-        let penv = Penv::new(home.clone())?;
+        // The hooks always pass the shell, so they never read the configuration.
+        let shell = Shell::or_configured(&self.shell, &home)?;
+        if let Shell::Unsupported = shell {
+            return Err(anyhow!(
+                "please provide a supported shell: `zsh`, `bash`, `fish` or `nu`"
+            ));
+        }
+
+        // The hooks run this before every prompt, so the common case of nothing having
+        // changed is answered without loading the penv state at all.
+        let key = state_key(&home, &[&shell.to_string()]);
+        if self.if_changed.as_deref() == Some(key.as_str()) {
+            return Ok(());
+        }
+
+        let snapshot_path = Snapshot::<String>::path(&home, &format!("env-{}", shell));
+        if let Some(snapshot) = Snapshot::<String>::read(&snapshot_path, &key) {
+            println!("{}", snapshot.value);
+            return Ok(());
+        }
+
+        let output = self.render(&shell, home, &key)?;
+        println!("{}", output);
+        let snapshot = Snapshot { key, value: output };
+        if let Err(e) = snapshot.write(&snapshot_path) {
+            tracing::debug!("failed to write snapshot {}: {}", snapshot_path, e);
+        }
+
+        Ok(())
+    }
+
+    fn render(&self, shell: &Shell, home: Utf8PathBuf, env_key: &str) -> Result<String> {
+        let penv = Penv::new(home)?;
 
         let mut context = tera::Context::new();
+        context.insert("env_key", env_key);
 
-        if let Some(active_environment) = &penv.active_environment {
-            context.insert(
-                "penv_active_environment",
-                &penv
-                    .active_environment
-                    .clone()
-                    .map(|e| e.metadata().alias.clone())
-                    .unwrap_or_default(),
-            );
-            if let Some(pcli_home) = penv.pcli_home() {
-                context.insert("pcli_home", &pcli_home);
-            }
-            if let Some(pclientd_home) = penv.pclientd_home() {
-                context.insert("pclientd_home", &pclientd_home);
-            }
-            if !active_environment.metadata().client_only {
-                if let Some(pd_home) = penv.pd_home() {
-                    context.insert("pd_home", &pd_home);
+        // An environment activated with `penv shell` or selected by a project file in the
+        // current directory tree takes precedence over the globally active environment.
+        // The hook re-runs this on every prompt, so the global environment is restored
+        // once the shell leaves the project tree.
+        let effective_environment =
+            match current_dir().and_then(|dir| penv.effective_environment(&dir)) {
+                Ok(effective_environment) => effective_environment,
+                Err(e) => {
+                    tracing::debug!("ignoring project file: {}", e);
+                    penv.active_environment
+                        .clone()
+                        .map(|environment| (environment, EnvironmentSource::Global))
                 }
+            };
+
+        let environment_variables = effective_environment
+            .as_ref()
+            .map(|(environment, _)| environment.environment_variables())
+            .unwrap_or_default();
+        let unset_variables = ENVIRONMENT_VARIABLE_NAMES
+            .iter()
+            .filter(|name| !environment_variables.iter().any(|v| v.name == **name))
+            .collect::<Vec<_>>();
+        context.insert("environment_variables", &environment_variables);
+        context.insert("unset_variables", &unset_variables);
 
-                context.insert("pd_join_url", &active_environment.metadata().pd_join_url);
-                context.insert(
-                    "pd_cometbft_proxy_url",
-                    &active_environment.metadata().pd_join_url,
-                );
-                context.insert("cometbft_home", &penv.cometbft_home());
+        // The directories penv puts on the PATH are recorded in `PENV_PATH_ADDED`, so the
+        // next run can take them off again instead of adding them a second time.
+        let mut path_added = Vec::new();
+        if let Some((active_environment, source)) = &effective_environment {
+            // The global `bin` symlink points at the globally active environment, so a
+            // scoped environment's own `bin` is put in front of it on the PATH.
+            if let EnvironmentSource::ProjectFile(project_file) = source {
+                context.insert("penv_project_file", project_file);
             }
+            if *source != EnvironmentSource::Global {
+                context.insert("penv_environment_bin", &active_environment.bin_dir());
+                path_added.push(active_environment.bin_dir().to_string());
+            }
+            path_added.push(penv.path_string());
         }
+        context.insert("path_added", &path_added.join(":"));
+        context.insert("path_remove", &penv.path_string());
 
-        context.insert("path_add", &penv.path_string());
+        shell.render(template(shell), &context)
+    }
+}
 
-        match self.shell {
-            Shell::Bash => self.print_bash(&context),
-            Shell::Zsh => self.print_zsh(&context),
-            Shell::Unsupported => Err(anyhow!("please provide a supported shell: `zsh` or `bash`")),
-        }
+fn template(shell: &Shell) -> &'static str {
+    match shell {
+        Shell::Bash => include_str!("../../files/bash-env.j2"),
+        Shell::Zsh => include_str!("../../files/zsh-env.j2"),
+        Shell::Fish => include_str!("../../files/fish-env.j2"),
+        Shell::Nu => include_str!("../../files/nu-env.j2"),
+        Shell::Unsupported => unreachable!("checked before rendering"),
     }
+}
 
-    fn print_zsh(&self, context: &tera::Context) -> Result<()> {
-        let hook_template = include_str!("../../files/zsh-env.j2");
-        let hook = tera::Tera::one_off(hook_template, context, false)?;
-        println!("{}", hook);
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
 
-        Ok(())
-    }
+    use super::*;
+    use crate::penv::environment::{shell_quote, EnvironmentVariable};
 
-    fn print_bash(&self, context: &tera::Context) -> Result<()> {
-        let hook_template = include_str!("../../files/bash-env.j2");
-        let hook = tera::Tera::one_off(hook_template, context, false)?;
-        println!("{}", hook);
+    #[test]
+    fn quote_untrusted_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::from_path_buf(dir.path().to_owned()).unwrap();
+        let marker = dir.join("ran");
+        let project_dir = dir.join(format!("it's \"x$(touch {})\" `touch {}`", marker, marker));
+        let project_file = project_dir.join(".penv.toml");
+        let bin_dir = project_dir.join("bin");
 
-        Ok(())
+        let mut context = tera::Context::new();
+        context.insert("env_key", "key");
+        context.insert(
+            "environment_variables",
+            &[EnvironmentVariable {
+                name: "PCLI_HOME",
+                value: project_dir.join("pcli").to_string(),
+            }],
+        );
+        context.insert("unset_variables", &Vec::<&str>::new());
+        context.insert("path_added", &bin_dir);
+        context.insert("path_remove", &dir.join("bin"));
+        context.insert("penv_project_file", &project_file);
+        context.insert("penv_environment_bin", &bin_dir);
+
+        let script = Shell::Bash
+            .render(template(&Shell::Bash), &context)
+            .unwrap();
+        let output = Command::new("bash")
+            .arg("-c")
+            .arg(format!(
+                "{}\nprintf '%s\\n' \"$PENV_PROJECT_FILE\" \"$PENV_ENVIRONMENT_BIN\" \"$PENV_PATH_ADDED\" \"$PCLI_HOME\"",
+                script
+            ))
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!(
+                "{}\n{}\n{}\n{}\n",
+                project_file,
+                bin_dir,
+                bin_dir,
+                project_dir.join("pcli")
+            )
+        );
+        assert!(!marker.exists());
+
+        let script = Shell::Zsh.render(template(&Shell::Zsh), &context).unwrap();
+        assert!(script.contains(&format!(
+            "export PENV_PROJECT_FILE={}",
+            shell_quote(project_file.as_str())
+        )));
+
+        let script = Shell::Fish
+            .render(template(&Shell::Fish), &context)
+            .unwrap();
+        assert!(script.contains(&format!(
+            "set -gx PENV_PROJECT_FILE '{}'",
+            project_file.as_str().replace('\'', "\\'")
+        )));
+
+        let script = Shell::Nu.render(template(&Shell::Nu), &context).unwrap();
+        let values: serde_json::Value = serde_json::from_str(&script).unwrap();
+        assert_eq!(values["project_file"], project_file.as_str());
     }
 }
@@ -0,0 +1,91 @@
+use std::{env, path::PathBuf, process};
+
+use anyhow::{anyhow, Context as _, Result};
+use camino::Utf8PathBuf;
+
+use crate::penv::environment::{EnvironmentTrait as _, ENVIRONMENT_VARIABLE_NAMES};
+use crate::penv::project::SHELL_ENVIRONMENT_VAR;
+use crate::penv::Penv;
+
+#[derive(Debug, clap::Parser)]
+pub struct ExecCmd {
+    /// The alias of the Penumbra environment to run the command in.
+    environment_alias: String,
+    /// The command to run, followed by its arguments.
+    #[clap(required = true, last = true)]
+    command: Vec<String>,
+}
+
+impl ExecCmd {
+    pub async fn exec(&self, home: Utf8PathBuf) -> Result<()> {
+        let penv = Penv::new(home.clone())?;
+        let environment = penv
+            .environments
+            .get_environment(&self.environment_alias)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Environment with alias {} does not exist",
+                    self.environment_alias
+                )
+            })?;
+
+        let (program, args) = self.command.split_first().expect("clap requires a command");
+
+        let mut command = process::Command::new(program);
+        command.args(args);
+
+        for name in ENVIRONMENT_VARIABLE_NAMES {
+            command.env_remove(name);
+        }
+        let environment_variables = environment.environment_variables();
+        command.env(
+            "PENV_VARIABLES_SET",
+            environment_variables
+                .iter()
+                .map(|variable| variable.name)
+                .collect::<Vec<_>>()
+                .join(":"),
+        );
+        for variable in environment_variables {
+            command.env(variable.name, variable.value);
+        }
+        // Nested `penv` invocations should resolve to the same environment.
+        command.env(SHELL_ENVIRONMENT_VAR, &self.environment_alias);
+        command.env_remove("PENV_PROJECT_FILE");
+        command.env_remove("PENV_ENVIRONMENT_BIN");
+
+        // Only the environment's own binaries should be reachable, so any penv-managed
+        // directories are dropped from the inherited PATH.
+        let path_added = env::var_os("PENV_PATH_ADDED").unwrap_or_default();
+        let penv_dirs = std::iter::once(PathBuf::from(penv.path_string()))
+            .chain(env::split_paths(&path_added))
+            .collect::<Vec<_>>();
+        let inherited_path = env::var_os("PATH").unwrap_or_default();
+        let path = env::join_paths(
+            std::iter::once(environment.bin_dir().into_std_path_buf())
+                .chain(env::split_paths(&inherited_path).filter(|dir| !penv_dirs.contains(dir))),
+        )
+        .context("Failed to construct PATH")?;
+        command.env("PATH", path);
+        command.env("PENV_PATH_ADDED", environment.bin_dir());
+
+        tracing::debug!(?command, "running command");
+
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::process::CommandExt as _;
+
+            // Replace the penv process, so signals and the exit status go straight
+            // to the caller.
+            let e = command.exec();
+            Err(anyhow!(e).context(format!("Failed to run {}", program)))
+        }
+        #[cfg(not(target_family = "unix"))]
+        {
+            let status = command
+                .status()
+                .with_context(|| format!("Failed to run {}", program))?;
+            process::exit(status.code().unwrap_or(1));
+        }
+    }
+}
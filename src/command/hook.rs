@@ -1,28 +1,87 @@
-use std::env;
+use std::collections::HashMap;
+use std::{env, fmt};
 
 use anyhow::{anyhow, Result};
-use camino::Utf8PathBuf;
-use serde::Serialize;
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::penv::config::Config;
+use crate::penv::environment::shell_quote;
 
 #[derive(Debug, clap::Parser)]
 pub struct HookCmd {
-    /// The shell to output hook scripts for.
-    #[clap(default_value_t, value_enum)]
-    shell: Shell,
+    /// The shell to output hook scripts for. Defaults to the `shell` setting.
+    #[clap(value_enum)]
+    shell: Option<Shell>,
 }
 
 // Supported shells for configuring penv environments
-#[derive(Debug, Clone, Default, clap::ValueEnum, Serialize)]
+#[derive(Debug, Clone, Default, Eq, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum Shell {
     Bash,
     Zsh,
+    Fish,
+    Nu,
     #[default]
     Unsupported,
 }
 
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+            Shell::Nu => "nu",
+            Shell::Unsupported => "unsupported",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Shell {
+    /// The given shell, or the one configured with the `shell` setting if none is given.
+    pub fn or_configured(shell: &Option<Shell>, home: &Utf8Path) -> Result<Shell> {
+        match shell {
+            Some(shell) => Ok(shell.clone()),
+            None => Ok(Config::load(home)?.shell().unwrap_or_default()),
+        }
+    }
+
+    /// Quotes a string for use as a single word in this shell's scripts.
+    pub fn quote(&self, s: &str) -> String {
+        match self {
+            // Within single quotes, fish only treats backslashes and single quotes specially.
+            Shell::Fish => format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'")),
+            // nushell's double-quoted strings take the same escapes as JSON.
+            Shell::Nu => serde_json::Value::from(s).to_string(),
+            Shell::Bash | Shell::Zsh | Shell::Unsupported => shell_quote(s),
+        }
+    }
+
+    /// Renders a script for this shell from `template`, which can quote values that
+    /// aren't controlled by penv with the `quote` filter.
+    pub fn render(&self, template: &str, context: &tera::Context) -> Result<String> {
+        let shell = self.clone();
+        let mut tera = tera::Tera::default();
+        tera.register_filter(
+            "quote",
+            move |value: &tera::Value, _: &HashMap<String, tera::Value>| {
+                let value = match value {
+                    tera::Value::String(s) => s.clone(),
+                    value => value.to_string(),
+                };
+                Ok(tera::Value::String(shell.quote(&value)))
+            },
+        );
+
+        Ok(tera.render_str(template, context)?)
+    }
+}
+
 impl HookCmd {
-    pub async fn exec(&self, _home: Utf8PathBuf) -> Result<()> {
+    pub async fn exec(&self, home: Utf8PathBuf) -> Result<()> {
         // Look up the path for the currently executed `penv`, so we can use the fullpath
         // in the shell configs. This ensures that the sourced shell will work,
         // even if penv isn't on PATH already.
@@ -35,7 +94,7 @@ impl HookCmd {
         let mut context = tera::Context::new();
         context.insert("penv_executable", &current_exe);
 
-        let shell = self.shell.clone();
+        let shell = Shell::or_configured(&self.shell, &home)?;
         match shell {
             Shell::Zsh => {
                 let hook_template = include_str!("../../files/zsh-hook.j2");
@@ -47,10 +106,51 @@ impl HookCmd {
                 let hook = tera::Tera::one_off(hook_template, &context, false)?;
                 println!("{}", hook);
             }
+            Shell::Fish => {
+                let hook_template = include_str!("../../files/fish-hook.j2");
+                let hook = tera::Tera::one_off(hook_template, &context, false)?;
+                println!("{}", hook);
+            }
+            Shell::Nu => {
+                let hook_template = include_str!("../../files/nu-hook.j2");
+                let hook = tera::Tera::one_off(hook_template, &context, false)?;
+                println!("{}", hook);
+            }
             Shell::Unsupported => {
-                return Err(anyhow!("please provide a supported shell: `zsh` or `bash`"))
+                return Err(anyhow!(
+                    "please provide a supported shell: `zsh`, `bash`, `fish` or `nu`"
+                ))
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Shell;
+
+    #[test]
+    fn quote_for_each_shell() {
+        let alias = "it's \"$(touch pwned)\" `id` \\n";
+
+        let output = std::process::Command::new("bash")
+            .arg("-c")
+            .arg(format!(
+                "alias={}; printf '%s' \"$alias\"",
+                Shell::Bash.quote(alias)
+            ))
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), alias);
+
+        assert_eq!(
+            Shell::Fish.quote(alias),
+            "'it\\'s \"$(touch pwned)\" `id` \\\\n'"
+        );
+        assert_eq!(
+            Shell::Nu.quote(alias),
+            "\"it's \\\"$(touch pwned)\\\" `id` \\\\n\""
+        );
+    }
+}
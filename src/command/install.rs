@@ -3,7 +3,10 @@ use camino::Utf8PathBuf;
 use clap::value_parser;
 use target_lexicon::Triple;
 
-use crate::penv::{release::RepoOrVersionReq, Penv};
+use crate::penv::{
+    release::{ReleaseChannel, RepoOrVersionReq, TagPattern},
+    Penv,
+};
 
 // TODO: the cometbft version being used must be matched to the penumbra versions,
 // so it might be desirable to add support for managing cometbft installations here as well.
@@ -12,9 +15,17 @@ use crate::penv::{release::RepoOrVersionReq, Penv};
 pub struct InstallCmd {
     /// The GitHub repository to fetch releases from.
     ///
-    /// Defaults to "penumbra-zone/penumbra"
-    #[clap(long, default_value = "penumbra-zone/penumbra")]
-    repository_name: String,
+    /// Releases from different repositories are installed side by side, even if their
+    /// versions are the same. Defaults to the `repository_name` setting, which is
+    /// "penumbra-zone/penumbra" unless configured otherwise.
+    #[clap(long)]
+    repository_name: Option<String>,
+    /// The pattern mapping the repository's release tags to versions, e.g. "penumbra-{version}".
+    ///
+    /// Once set, the pattern is remembered for later commands using the same repository.
+    /// Defaults to "v{version}".
+    #[clap(long)]
+    tag_pattern: Option<TagPattern>,
     /// The version of the Penumbra software suite to install.
     ///
     /// This can install either from a git repo (locally or remotely) or from a version requirement.
@@ -32,16 +43,33 @@ pub struct InstallCmd {
     /// Version requirements are specified as a semver version requirement, i.e. "0.79" will install the latest 0.79.x release.
     #[clap(value_parser = value_parser!(RepoOrVersionReq))]
     penumbra_version: RepoOrVersionReq,
+    /// The release channel a "latest" version requirement resolves against.
+    ///
+    /// "stable" only considers stable releases, "prerelease" also considers prereleases,
+    /// and "all" also considers draft releases. Defaults to the `channel` setting.
+    #[clap(long, value_enum)]
+    channel: Option<ReleaseChannel>,
 }
 
 impl InstallCmd {
     pub async fn exec(&self, home: Utf8PathBuf) -> Result<()> {
-        let repository_name = &self.repository_name;
-
         println!("installing {}", self.penumbra_version);
-        let mut penv = Penv::new_from_repository(repository_name.clone(), home.clone())?;
-        penv.install_release(self.penumbra_version.clone(), Triple::host())
-            .await?;
+        let mut penv = Penv::new(home.clone())?;
+        let repository_name = self
+            .repository_name
+            .clone()
+            .unwrap_or_else(|| penv.config.repository_name());
+        if let Some(tag_pattern) = &self.tag_pattern {
+            penv.set_tag_pattern(&repository_name, tag_pattern.clone())?;
+        }
+        let channel = self.channel.unwrap_or_else(|| penv.config.channel());
+        penv.install_release(
+            &repository_name,
+            self.penumbra_version.clone(),
+            channel,
+            Triple::host(),
+        )
+        .await?;
 
         Ok(())
     }
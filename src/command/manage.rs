@@ -1,17 +1,29 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context as _, Result};
 use camino::Utf8PathBuf;
 use clap::value_parser;
 use colored::Colorize;
 use semver::Version;
+use serde::Serialize;
 use std::io::{self, IsTerminal};
+use std::sync::Arc;
+use target_lexicon::Triple;
 use url::Url;
 
+use super::OutputFormat;
 use crate::penv::{
     environment::{Environment, EnvironmentTrait as _, ManagedFile as _},
-    release::{InstalledRelease, RepoOrVersion, RepoOrVersionReq},
+    release::{ReleaseChannel, RepoOrVersion, RepoOrVersionReq, TagPattern},
     Penv,
 };
 
+/// The output of `penv manage list`.
+#[derive(Debug, Serialize)]
+struct EnvironmentList<'a> {
+    /// The alias of the globally active environment.
+    active_environment: Option<String>,
+    environments: &'a [Arc<Environment>],
+}
+
 /// Safely prompt for seed phrase secret interactively.
 fn get_seed_phrase() -> Result<String> {
     if io::stdin().is_terminal() {
@@ -50,15 +62,21 @@ pub enum ManageTopSubCmd {
     /// Rename a configured Penumbra environment.
     #[clap(display_order = 300)]
     Rename(RenameCmd),
-    // Migrate will handle applying migrations between state-breaking versions
-    // #[clap(flatten)]
-    // Migrate(MigrateSubCmd),
+    /// Change the URLs or client-only mode of a Penumbra environment, keeping its wallet.
+    #[clap(display_order = 310)]
+    Edit(EditCmd),
+    /// Migrate a Penumbra environment to a new, state-breaking software version.
+    #[clap(display_order = 320)]
+    Migrate(MigrateCmd),
     /// Set a Penumbra environment to use an exact software version.
     #[clap(display_order = 350)]
     Set(SetCmd),
     /// Upgrade a Penumbra environment to use the latest software version matching its semver version requirement.
     #[clap(display_order = 400)]
     Upgrade(UpgradeCmd),
+    /// Roll a Penumbra environment back to a previously pinned software version.
+    #[clap(display_order = 450)]
+    Rollback(RollbackCmd),
     /// Display information about a specific Penumbra environment.
     #[clap(display_order = 500)]
     Info(InfoCmd),
@@ -87,6 +105,14 @@ pub struct CreateCmd {
     /// If a matching version is not installed, penv will attempt to install it.
     #[clap(long, value_parser = value_parser!(RepoOrVersionReq))]
     penumbra_version: RepoOrVersionReq,
+    /// The release channel a "latest" version requirement resolves against.
+    ///
+    /// "stable" only considers stable releases, "prerelease" also considers prereleases,
+    /// and "all" also considers draft releases. Defaults to the `channel` setting.
+    ///
+    /// The channel is stored with the environment and used by later upgrades.
+    #[clap(long, value_enum)]
+    channel: Option<ReleaseChannel>,
     /// The GRPC URL to use to connect to a fullnode.
     ///
     /// If pd configs are also being generated, this should typically be localhost:8080
@@ -94,15 +120,18 @@ pub struct CreateCmd {
     grpc_url: Url,
     /// The URL to use for `pd network join` operations, aka the cometBFT RPC endpoint.
     ///
-    /// Typically this runs on port 26657. If not supplied, this will
-    /// default to the GRPC URL with the port changed to 26657 and HTTP protocol.
+    /// Typically this runs on port 26657. If not supplied, this will default to the GRPC URL
+    /// with the HTTP protocol and the port changed to the `join_port` setting, 26657 unless
+    /// configured otherwise.
     #[clap(long, parse(try_from_str = Url::parse))]
     pd_join_url: Option<Url>,
-    /// The GitHub repository to fetch releases from if an installation is necessary.
+    /// The GitHub repository whose releases the environment uses.
     ///
-    /// Defaults to "penumbra-zone/penumbra"
-    #[clap(long, default_value = "penumbra-zone/penumbra")]
-    repository_name: String,
+    /// The repository is stored with the environment and used by later upgrades.
+    /// Defaults to the `repository_name` setting, which is "penumbra-zone/penumbra" unless
+    /// configured otherwise.
+    #[clap(long)]
+    repository_name: Option<String>,
     /// Disable setting up a fullnode installation.
     #[clap(long)]
     client_only: bool,
@@ -146,6 +175,50 @@ pub struct RenameCmd {
     new_alias: String,
 }
 
+#[derive(Debug, Clone, clap::Parser)]
+#[clap(group(
+    clap::ArgGroup::new("changes")
+        .required(true)
+        .multiple(true)
+        .args(&["grpc-url", "pd-join-url", "client-only"])
+))]
+pub struct EditCmd {
+    /// The alias of the Penumbra environment to be edited.
+    #[clap(display_order = 100)]
+    environment_alias: String,
+    /// The GRPC URL pcli and pclientd connect to.
+    ///
+    /// Their configs are updated in place.
+    #[clap(long, parse(try_from_str = Url::parse))]
+    grpc_url: Option<Url>,
+    /// The URL to use for `pd network join` operations, aka the cometBFT RPC endpoint.
+    ///
+    /// A node that already joined a network stays on it; the URL is used when a
+    /// client-only environment is switched to a full node, and for the environment's
+    /// `PENUMBRA_PD_JOIN_URL` and `PENUMBRA_PD_COMETBFT_PROXY_URL` variables.
+    #[clap(long, parse(try_from_str = Url::parse))]
+    pd_join_url: Option<Url>,
+    /// Whether the environment runs only clients, e.g. `--client-only=false` to add a node.
+    ///
+    /// Adding a node joins the network at the pd join URL, or generates a dev network
+    /// if the environment was created with `--generate-network`. Removing the node
+    /// keeps its data, so that it's reused if the node is added back.
+    #[clap(long, value_name = "BOOL", require_equals = true, value_parser = value_parser!(bool))]
+    client_only: Option<bool>,
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+pub struct MigrateCmd {
+    /// The alias of the Penumbra environment to be migrated.
+    #[clap(display_order = 100)]
+    environment_alias: String,
+    /// The exact version to migrate the environment to (e.g. "2.0.0").
+    ///
+    /// The version must already be installed.
+    #[clap(long, display_order = 200)]
+    to: String,
+}
+
 #[derive(Debug, Clone, clap::Parser)]
 pub struct SetCmd {
     /// The alias of the Penumbra environment to update.
@@ -157,15 +230,42 @@ pub struct SetCmd {
 }
 
 #[derive(Debug, Clone, clap::Parser)]
-pub struct UpgradeCmd {
-    /// The alias of the Penumbra environment to be upgraded.
+pub struct RollbackCmd {
+    /// The alias of the Penumbra environment to roll back.
     #[clap(display_order = 100)]
     environment_alias: String,
-    /// The GitHub repository to fetch releases from.
+    /// The version from the environment's pinned version history to roll back to (e.g. "1.5.2").
     ///
-    /// Defaults to "penumbra-zone/penumbra"
-    #[clap(long, default_value = "penumbra-zone/penumbra")]
-    repository_name: String,
+    /// Defaults to the version pinned before the current one.
+    #[clap(long, display_order = 200)]
+    to: Option<String>,
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+pub struct UpgradeCmd {
+    /// The alias of the Penumbra environment to be upgraded.
+    #[clap(display_order = 100, required_unless_present = "all")]
+    environment_alias: Option<String>,
+    /// Upgrade every binary environment instead of a single one.
+    #[clap(long, conflicts_with = "environment-alias")]
+    all: bool,
+    /// Check the environment's repository for newer releases matching the version requirement,
+    /// installing the newest one before re-pinning.
+    ///
+    /// Without this flag, only versions already installed in the cache are considered.
+    #[clap(long)]
+    fetch: bool,
+    /// The release channel to resolve a "latest" version requirement against,
+    /// instead of the channel stored with each environment.
+    #[clap(long, value_enum)]
+    channel: Option<ReleaseChannel>,
+    /// The pattern mapping the environment's repository's release tags to versions,
+    /// e.g. "penumbra-{version}".
+    ///
+    /// Once set, the pattern is remembered for later commands using the same repository.
+    /// Defaults to "v{version}".
+    #[clap(long, conflicts_with = "all")]
+    tag_pattern: Option<TagPattern>,
 }
 
 #[derive(Debug, Clone, clap::Parser)]
@@ -189,7 +289,7 @@ pub struct ResetCmd {
 }
 
 impl ManageCmd {
-    pub async fn exec(&self, home: Utf8PathBuf) -> Result<()> {
+    pub async fn exec(&self, home: Utf8PathBuf, output: OutputFormat) -> Result<()> {
         match self {
             ManageCmd {
                 subcmd:
@@ -202,19 +302,24 @@ impl ManageCmd {
                         client_only,
                         generate_network,
                         import_seed_phrase,
+                        channel,
                     }),
             } => {
+                let mut penv = Penv::new(home.clone())?;
+
                 let pd_join_url = match pd_join_url {
                     Some(url) => url.clone(),
                     None => {
                         let mut grpc_url = grpc_url.clone();
-                        grpc_url.set_port(Some(26657)).unwrap();
+                        grpc_url.set_port(Some(penv.config.join_port())).unwrap();
                         grpc_url.set_scheme("http").unwrap();
                         grpc_url
                     }
                 };
-
-                let mut penv = Penv::new_from_repository(repository_name.clone(), home.clone())?;
+                let repository_name = repository_name
+                    .clone()
+                    .unwrap_or_else(|| penv.config.repository_name());
+                let channel = channel.unwrap_or_else(|| penv.config.channel());
 
                 // Handle seed phrase input if import_seed_phrase is true
                 let seed_phrase = if *import_seed_phrase {
@@ -228,10 +333,11 @@ impl ManageCmd {
                     penumbra_version.clone(),
                     grpc_url.clone(),
                     pd_join_url.clone(),
-                    repository_name.clone(),
+                    repository_name,
                     client_only.clone(),
                     generate_network.clone(),
                     seed_phrase,
+                    channel,
                 )?;
 
                 match *env {
@@ -269,9 +375,10 @@ impl ManageCmd {
 
                 let info = penv.environment_info(environment_alias.clone())?;
 
-                println!("{}", info);
-
-                Ok(())
+                output.print(info, || {
+                    println!("{}", info);
+                    Ok(())
+                })
             }
             ManageCmd {
                 subcmd: ManageTopSubCmd::List(ListCmd { detailed }),
@@ -281,31 +388,38 @@ impl ManageCmd {
                 let environments = penv.environments()?;
                 let active_environment = penv.active_environment.clone();
 
-                println!("Environments:");
-                for environment in environments.iter() {
-                    if *detailed {
-                        if active_environment
+                let list = EnvironmentList {
+                    active_environment: active_environment
+                        .as_ref()
+                        .map(|e| e.metadata().alias.clone()),
+                    environments,
+                };
+                output.print(&list, || {
+                    println!("Environments:");
+                    for environment in environments.iter() {
+                        if *detailed {
+                            if active_environment
+                                .clone()
+                                .is_some_and(|e| e.metadata().alias == environment.metadata().alias)
+                            {
+                                print!("{}", format!("{}Active: true\n\n", environment).green());
+                            } else {
+                                print!("{}", format!("{}Active: false\n\n", environment).red());
+                            }
+                        } else if active_environment
                             .clone()
                             .is_some_and(|e| e.metadata().alias == environment.metadata().alias)
                         {
-                            print!("{}", format!("{}Active: true\n\n", environment).green());
+                            print!(
+                                "{}",
+                                format!("{} (active)\n\n", environment.metadata().alias).green()
+                            );
                         } else {
-                            print!("{}", format!("{}Active: false\n\n", environment).red());
+                            print!("{}", format!("{}\n\n", environment.metadata().alias).red());
                         }
-                    } else if active_environment
-                        .clone()
-                        .is_some_and(|e| e.metadata().alias == environment.metadata().alias)
-                    {
-                        print!(
-                            "{}",
-                            format!("{} (active)\n\n", environment.metadata().alias).green()
-                        );
-                    } else {
-                        print!("{}", format!("{}\n\n", environment.metadata().alias).red());
                     }
-                }
-
-                Ok(())
+                    Ok(())
+                })
             }
             ManageCmd {
                 subcmd:
@@ -347,22 +461,27 @@ impl ManageCmd {
                         )
                     })?;
 
-                match *environment {
-                    Environment::BinaryEnvironment(ref _env) => {}
+                let repository_name = match *environment {
+                    Environment::BinaryEnvironment(ref env) => env.repository_name.clone(),
                     Environment::CheckoutEnvironment(_) => {
                         return Err(anyhow!("Cannot set version for a checkout environment"));
                     }
-                }
+                };
 
                 // Verify the version is installed in the cache
                 if penv
                     .cache
-                    .get_installed_release(&RepoOrVersion::Version(version.clone()))
+                    .get_installed_release(
+                        &repository_name,
+                        &RepoOrVersion::Version(version.clone()),
+                    )
                     .is_none()
                 {
                     return Err(anyhow!(
-                        "Version {} is not installed; run `penv install {}` first",
+                        "Version {} from {} is not installed; run `penv install --repository-name {} {}` first",
                         version,
+                        repository_name,
+                        repository_name,
                         version
                     ));
                 }
@@ -377,70 +496,166 @@ impl ManageCmd {
             }
             ManageCmd {
                 subcmd:
-                    ManageTopSubCmd::Upgrade(UpgradeCmd {
+                    ManageTopSubCmd::Edit(EditCmd {
                         environment_alias,
-                        repository_name,
+                        grpc_url,
+                        pd_join_url,
+                        client_only,
                     }),
             } => {
-                let mut penv = Penv::new_from_repository(repository_name.clone(), home.clone())?;
+                let mut penv = Penv::new(home.clone())?;
 
-                let environment = penv.environments.get_environment(environment_alias);
-                if environment.is_none() {
-                    return Err(anyhow!(
-                        "Environment with alias {} does not exist",
-                        environment_alias
-                    ));
-                }
+                let env = penv.edit_environment(
+                    environment_alias,
+                    grpc_url.clone(),
+                    pd_join_url.clone(),
+                    *client_only,
+                )?;
 
-                let environment = environment.unwrap();
+                println!("updated environment {}", environment_alias);
+                print!("{}", env);
 
-                let (penumbra_version, pinned_version) = match *environment {
-                    Environment::BinaryEnvironment(ref env) => (
-                        RepoOrVersionReq::VersionReqOrLatest(env.version_requirement.clone()),
-                        env.pinned_version.clone(),
-                    ),
-                    Environment::CheckoutEnvironment(ref _env) => {
-                        panic!("checkout environments are not supported for upgrades")
-                    }
+                Ok(())
+            }
+            ManageCmd {
+                subcmd:
+                    ManageTopSubCmd::Migrate(MigrateCmd {
+                        environment_alias,
+                        to,
+                    }),
+            } => {
+                let version =
+                    Version::parse(to).map_err(|e| anyhow!("Invalid version '{}': {}", to, e))?;
+
+                let mut penv = Penv::new(home.clone())?;
+
+                println!(
+                    "migrating environment {} to version {}",
+                    environment_alias, version
+                );
+                penv.migrate_environment(environment_alias.clone(), version)?;
+
+                Ok(())
+            }
+            ManageCmd {
+                subcmd:
+                    ManageTopSubCmd::Rollback(RollbackCmd {
+                        environment_alias,
+                        to,
+                    }),
+            } => {
+                let to = to
+                    .as_ref()
+                    .map(|to| {
+                        Version::parse(to).map_err(|e| anyhow!("Invalid version '{}': {}", to, e))
+                    })
+                    .transpose()?;
+
+                let mut penv = Penv::new(home.clone())?;
+                let version = penv.rollback_environment(environment_alias.clone(), to)?;
+
+                println!(
+                    "rolled back environment {} to version {}",
+                    environment_alias, version
+                );
+
+                Ok(())
+            }
+            ManageCmd {
+                subcmd:
+                    ManageTopSubCmd::Upgrade(UpgradeCmd {
+                        environment_alias,
+                        all,
+                        fetch,
+                        channel,
+                        tag_pattern,
+                    }),
+            } => {
+                let mut penv = Penv::new(home.clone())?;
+                if let (Some(tag_pattern), Some(environment_alias)) =
+                    (tag_pattern, environment_alias)
+                {
+                    let environment = penv
+                        .environments
+                        .get_environment(environment_alias)
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "Environment with alias {} does not exist",
+                                environment_alias
+                            )
+                        })?;
+                    let repository_name = match *environment {
+                        Environment::BinaryEnvironment(ref env) => env.repository_name.clone(),
+                        Environment::CheckoutEnvironment(_) => {
+                            return Err(anyhow!(
+                                "checkout environments are not supported for upgrades"
+                            ));
+                        }
+                    };
+                    penv.set_tag_pattern(&repository_name, tag_pattern.clone())?;
+                }
+
+                let environment_aliases = if *all {
+                    penv.environments
+                        .iter()
+                        .filter(|e| matches!(***e, Environment::BinaryEnvironment(_)))
+                        .map(|e| e.metadata().alias.clone())
+                        .collect::<Vec<_>>()
+                } else {
+                    environment_alias.iter().cloned().collect()
                 };
 
-                // Find the best matching version
-                let cache = &penv.cache;
-                let matching_installed_version = match cache.find_best_match(&penumbra_version) {
-                    Some(installed_version) => installed_version,
-                    None => {
-                        // TODO: allow auto-installing here
-                        return Err(anyhow!(
-                            "No installed version found for version requirement {}",
-                            penumbra_version
-                        ));
+                if !*all {
+                    for environment_alias in environment_aliases {
+                        penv.upgrade_environment(
+                            &environment_alias,
+                            *fetch,
+                            *channel,
+                            Triple::host(),
+                        )
+                        .await?;
                     }
-                };
+                    return Ok(());
+                }
 
-                match *matching_installed_version {
-                    InstalledRelease::GitCheckout(ref _release) => {
-                        unreachable!("git checkout environments are not supported for upgrades")
+                // One environment failing to upgrade shouldn't hold back the others.
+                let mut results = Vec::new();
+                for environment_alias in environment_aliases {
+                    let result = penv
+                        .upgrade_environment(&environment_alias, *fetch, *channel, Triple::host())
+                        .await
+                        .with_context(|| {
+                            format!("Failed to upgrade environment {}", environment_alias)
+                        });
+                    if let Err(e) = &result {
+                        eprintln!("{:#}", e);
                     }
-                    InstalledRelease::Binary(ref matching_installed_version) => {
-                        if matching_installed_version.version == pinned_version {
-                            println!(
-                                "Environment {}'s pinned version {} is the latest installed version matching version requirement {}",
-                                environment_alias, pinned_version, penumbra_version
-                            );
-                            return Ok(());
-                        }
+                    results.push((environment_alias, result));
+                }
 
-                        println!(
-                            "Updating environment {}'s pinned version from {} to the latest installed version {}",
-                            environment_alias, pinned_version, matching_installed_version.version
-                        );
-                        penv.replace_version(
-                            environment_alias.clone(),
-                            matching_installed_version.version.clone(),
-                        )?;
-                        penv.persist()?;
+                println!("Upgrade summary:");
+                let mut failed = 0;
+                for (environment_alias, result) in &results {
+                    match result {
+                        Ok(Some(version)) => {
+                            println!("  {}: upgraded to {}", environment_alias, version)
+                        }
+                        Ok(None) => println!("  {}: already up to date", environment_alias),
+                        Err(e) => {
+                            failed += 1;
+                            println!("  {}: failed: {}", environment_alias, e.root_cause())
+                        }
                     }
                 }
+
+                if failed > 0 {
+                    return Err(anyhow!(
+                        "failed to upgrade {} of {} environment(s)",
+                        failed,
+                        results.len()
+                    ));
+                }
+
                 Ok(())
             }
             &ManageCmd {
@@ -449,3 +664,49 @@ impl ManageCmd {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8Path;
+    use serde_json::json;
+
+    use super::*;
+    use crate::penv::penv::tests::test_penv;
+
+    #[test]
+    fn list_output_layout() {
+        let home = tempfile::tempdir().unwrap();
+        let home = Utf8Path::from_path(home.path()).unwrap();
+        let penv = test_penv(home);
+        let environments = [penv.environments.get_environment("binary").unwrap()];
+        let list = EnvironmentList {
+            active_environment: Some("binary".to_string()),
+            environments: &environments,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&list).unwrap(),
+            json!({
+                "active_environment": "binary",
+                "environments": [{
+                    "type": "BinaryEnvironment",
+                    "args": {
+                        "repository_name": "test/test",
+                        "pinned_version": "1.0.0",
+                        "version_requirement": { "type": "Latest" },
+                        "channel": "stable",
+                        "pin_history": [],
+                        "metadata": {
+                            "alias": "binary",
+                            "grpc_url": "http://localhost:8080/",
+                            "root_dir": home.join("environments/binary"),
+                            "pd_join_url": "http://localhost:26657/",
+                            "client_only": false,
+                            "generate_network": false,
+                        },
+                    },
+                }],
+            })
+        );
+    }
+}
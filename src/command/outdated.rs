@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use camino::Utf8PathBuf;
+use colored::Colorize;
+use semver::Version;
+use serde::Serialize;
+
+use super::OutputFormat;
+use crate::penv::{
+    environment::{Environment, EnvironmentTrait as _},
+    release::{InstalledRelease, ReleaseChannel, RepoOrVersionReq, VersionDrift},
+    Penv,
+};
+
+#[derive(Debug, clap::Parser)]
+pub struct OutdatedCmd {}
+
+/// The exit status when any environment is behind, which sets it apart from the status
+/// of 1 that penv exits with on errors.
+const BEHIND_EXIT_CODE: i32 = 3;
+
+/// The output of `penv outdated`.
+#[derive(Debug, Serialize)]
+struct OutdatedReport {
+    environments: Vec<EnvironmentStatus>,
+}
+
+/// The version status of a binary environment.
+#[derive(Debug, Serialize)]
+struct EnvironmentStatus {
+    alias: String,
+    repository_name: String,
+    version_requirement: String,
+    pinned_version: Version,
+    newest_installed: Option<Version>,
+    newest_available: Option<Version>,
+    /// How far the pinned version is behind the newest version, if it is.
+    drift: Option<VersionDrift>,
+    /// Whether the newest version is already installed.
+    update_installed: bool,
+    /// A prerelease ahead of both the newest and the pinned version, which is only
+    /// matched on the prerelease channel or by an explicit version requirement.
+    newer_prerelease: Option<Version>,
+    /// Why the available versions are unknown, if the releases couldn't be fetched.
+    upstream_error: Option<String>,
+}
+
+impl OutdatedCmd {
+    /// Prints the version status of every binary environment, exiting with
+    /// [`BEHIND_EXIT_CODE`] if any of them is behind the newest installed or available
+    /// version.
+    pub async fn exec(&self, home: Utf8PathBuf, output: OutputFormat) -> Result<()> {
+        let penv = Penv::new(home.clone())?;
+
+        let mut report = OutdatedReport {
+            environments: Vec::new(),
+        };
+        let mut fetch_errors: HashMap<String, String> = HashMap::new();
+        for environment in penv.environments.iter() {
+            let env = match **environment {
+                Environment::BinaryEnvironment(ref env) => env,
+                // Checkout environments have no version to compare against.
+                Environment::CheckoutEnvironment(_) => continue,
+            };
+
+            let required = RepoOrVersionReq::VersionReqOrLatest(env.version_requirement.clone());
+
+            let newest_installed =
+                match penv
+                    .cache
+                    .find_best_match(&env.repository_name, &required, env.channel)
+                {
+                    Some(InstalledRelease::Binary(r)) => Some(r.version.clone()),
+                    _ => None,
+                };
+
+            // A repository that can't be reached leaves the available versions unknown,
+            // rather than failing the whole report.
+            let upstream = match fetch_errors.get(&env.repository_name) {
+                Some(e) => Err(e.clone()),
+                None => newest_upstream(&penv, &env.repository_name, &required, env.channel)
+                    .await
+                    .map_err(|e| {
+                        let e = format!("{:#}", e);
+                        eprintln!(
+                            "failed to fetch the releases of {}: {}",
+                            env.repository_name, e
+                        );
+                        fetch_errors.insert(env.repository_name.clone(), e.clone());
+                        e
+                    }),
+            };
+            let (newest_available, newest_prerelease, upstream_error) = match upstream {
+                Ok((newest_available, newest_prerelease)) => {
+                    (newest_available, newest_prerelease, None)
+                }
+                Err(e) => (None, None, Some(e)),
+            };
+
+            let newest = newest_installed
+                .iter()
+                .chain(newest_available.iter())
+                .max()
+                .cloned()
+                .unwrap_or_else(|| env.pinned_version.clone());
+
+            report.environments.push(EnvironmentStatus {
+                alias: env.metadata().alias.clone(),
+                repository_name: env.repository_name.clone(),
+                version_requirement: env.version_requirement.to_string(),
+                pinned_version: env.pinned_version.clone(),
+                drift: VersionDrift::between(&env.pinned_version, &newest),
+                update_installed: newest_installed.as_ref() == Some(&newest),
+                newer_prerelease: newest_prerelease
+                    .filter(|p| *p > newest && *p > env.pinned_version),
+                newest_installed,
+                newest_available,
+                upstream_error,
+            });
+        }
+
+        output.print(&report, || {
+            println!(
+                "{:<24} {:<14} {:<16} {:<16} {:<16} STATUS",
+                "ENVIRONMENT", "REQUIREMENT", "PINNED", "INSTALLED", "AVAILABLE"
+            );
+            for status in &report.environments {
+                println!(
+                    "{:<24} {:<14} {:<16} {:<16} {:<16} {}",
+                    status.alias,
+                    status.version_requirement,
+                    status.pinned_version.to_string(),
+                    display_version(status.newest_installed.as_ref()),
+                    match status.upstream_error {
+                        Some(_) => "unknown".to_string(),
+                        None => display_version(status.newest_available.as_ref()),
+                    },
+                    display_status(status)
+                );
+            }
+            Ok(())
+        })?;
+
+        if report.environments.iter().any(|e| e.drift.is_some()) {
+            std::process::exit(BEHIND_EXIT_CODE);
+        }
+
+        Ok(())
+    }
+}
+
+fn display_status(status: &EnvironmentStatus) -> String {
+    let mut display = match status.drift {
+        None => "up to date".green(),
+        Some(drift) => {
+            let display = if status.update_installed {
+                format!("{} update installed", drift)
+            } else {
+                format!("{} update available", drift)
+            };
+
+            match drift {
+                VersionDrift::Major => display.red().bold(),
+                VersionDrift::Minor => display.yellow(),
+                VersionDrift::Patch => display.cyan(),
+                VersionDrift::Prerelease => display.magenta(),
+            }
+        }
+    }
+    .to_string();
+
+    // Prereleases are only matched on the prerelease channel or by an explicit
+    // version requirement, so mention them separately when they're ahead of everything else.
+    if let Some(prerelease) = &status.newer_prerelease {
+        display.push_str(&format!(" (prerelease {} available)", prerelease));
+    }
+    if status.upstream_error.is_some() {
+        display.push_str(" (available versions unknown)");
+    }
+
+    display
+}
+
+/// The newest version available upstream that matches the requirement on the channel,
+/// and the newest prerelease.
+///
+/// Each repository's releases are only fetched once, however many environments use it.
+async fn newest_upstream(
+    penv: &Penv,
+    repository_name: &str,
+    required: &RepoOrVersionReq,
+    channel: ReleaseChannel,
+) -> Result<(Option<Version>, Option<Version>)> {
+    let newest_prerelease = penv
+        .list_available(repository_name, None, ReleaseChannel::Prerelease)
+        .await?
+        .into_iter()
+        .filter(|(release, _installed)| release.is_prerelease())
+        .map(|(release, _installed)| release.version)
+        .max();
+
+    let newest_available = penv
+        .list_available(repository_name, Some(required), channel)
+        .await?
+        .into_iter()
+        .map(|(release, _installed)| release.version)
+        .max();
+
+    Ok((newest_available, newest_prerelease))
+}
+
+fn display_version(version: Option<&Version>) -> String {
+    version
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn output_layout() {
+        let report = OutdatedReport {
+            environments: vec![EnvironmentStatus {
+                alias: "testnet".to_string(),
+                repository_name: "penumbra-zone/penumbra".to_string(),
+                version_requirement: "^1".to_string(),
+                pinned_version: Version::new(1, 0, 0),
+                newest_installed: Some(Version::new(1, 1, 0)),
+                newest_available: None,
+                drift: Some(VersionDrift::Minor),
+                update_installed: true,
+                newer_prerelease: Some(Version::parse("2.0.0-rc.1").unwrap()),
+                upstream_error: None,
+            }],
+        };
+
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "environments": [{
+                    "alias": "testnet",
+                    "repository_name": "penumbra-zone/penumbra",
+                    "version_requirement": "^1",
+                    "pinned_version": "1.0.0",
+                    "newest_installed": "1.1.0",
+                    "newest_available": null,
+                    "drift": "minor",
+                    "update_installed": true,
+                    "newer_prerelease": "2.0.0-rc.1",
+                    "upstream_error": null,
+                }],
+            })
+        );
+    }
+}
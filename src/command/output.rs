@@ -0,0 +1,32 @@
+use anyhow::Result;
+use serde::Serialize;
+
+/// The format listing and info commands print in.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text, which may change between releases.
+    #[default]
+    Table,
+    /// JSON, following the schemas documented in the README.
+    Json,
+    /// TOML, following the same schemas as JSON.
+    Toml,
+}
+
+impl OutputFormat {
+    /// Prints `value` in the structured formats, or calls `table` to print the
+    /// human-readable text.
+    pub fn print<T: Serialize>(&self, value: &T, table: impl FnOnce() -> Result<()>) -> Result<()> {
+        match self {
+            OutputFormat::Table => table(),
+            OutputFormat::Json => {
+                println!("{}", serde_json::to_string_pretty(value)?);
+                Ok(())
+            }
+            OutputFormat::Toml => {
+                print!("{}", toml::to_string(value)?);
+                Ok(())
+            }
+        }
+    }
+}
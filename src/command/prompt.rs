@@ -0,0 +1,138 @@
+use anyhow::Result;
+use camino::{Utf8Path, Utf8PathBuf};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+use crate::penv::environment::{Environment, EnvironmentTrait as _, Network};
+use crate::penv::project::{current_dir, EnvironmentSource};
+use crate::penv::snapshot::{state_key, Snapshot};
+use crate::penv::Penv;
+
+#[derive(Debug, clap::Parser)]
+pub struct PromptCmd {
+    /// The template for the segment, which can use `alias`, `version`, `commit`,
+    /// `network`, `client_only` and `source`.
+    ///
+    /// `version` is the pinned version of binary environments, and the short commit of
+    /// the checkout of git checkout environments. `network` is one of `devnet`,
+    /// `testnet` and `mainnet`, and `source` one of `shell`, `project` and `global`.
+    #[clap(long, default_value = DEFAULT_FORMAT)]
+    format: String,
+    /// Color the segment by the kind of network the environment targets, in bold red
+    /// for mainnet.
+    #[clap(long)]
+    color: bool,
+}
+
+const DEFAULT_FORMAT: &str = "{{ alias }}@{{ version }}{% if client_only %} (client){% endif %}";
+
+/// What the prompt shows about the effective environment.
+#[derive(Debug, Serialize, Deserialize)]
+struct PromptSegment {
+    alias: String,
+    pinned_version: Option<String>,
+    /// The checkout of a git checkout environment. Its commit can change without penv
+    /// noticing, so it's looked up on every run.
+    checkout_dir: Option<Utf8PathBuf>,
+    network: Network,
+    client_only: bool,
+    source: String,
+}
+
+impl PromptCmd {
+    pub async fn exec(&self, home: Utf8PathBuf) -> Result<()> {
+        // The prompt is rendered all the time, so the segment is cached until the state
+        // the effective environment is derived from changes.
+        let key = state_key(&home, &["prompt"]);
+        let snapshot_path = Snapshot::<Option<PromptSegment>>::path(&home, "prompt");
+        let segment = match Snapshot::<Option<PromptSegment>>::read(&snapshot_path, &key) {
+            Some(snapshot) => snapshot.value,
+            None => {
+                let snapshot = Snapshot {
+                    key,
+                    value: prompt_segment(home)?,
+                };
+                if let Err(e) = snapshot.write(&snapshot_path) {
+                    tracing::debug!("failed to write snapshot {}: {}", snapshot_path, e);
+                }
+                snapshot.value
+            }
+        };
+
+        // Without an environment, there's no segment to show.
+        let segment = match segment {
+            Some(segment) => segment,
+            None => return Ok(()),
+        };
+
+        let commit = segment.checkout_dir.as_deref().and_then(checkout_commit);
+        let mut context = tera::Context::new();
+        context.insert("alias", &segment.alias);
+        context.insert(
+            "version",
+            segment
+                .pinned_version
+                .as_ref()
+                .or(commit.as_ref())
+                .map_or("unknown", String::as_str),
+        );
+        context.insert("commit", &commit.unwrap_or_default());
+        context.insert("network", &segment.network);
+        context.insert("client_only", &segment.client_only);
+        context.insert("source", &segment.source);
+        let output = tera::Tera::one_off(&self.format, &context, false)?;
+
+        if self.color {
+            colored::control::set_override(true);
+            match segment.network {
+                Network::Mainnet => println!("{}", output.red().bold()),
+                Network::Testnet => println!("{}", output.yellow()),
+                Network::Devnet => println!("{}", output.green()),
+            }
+        } else {
+            println!("{}", output);
+        }
+
+        Ok(())
+    }
+}
+
+fn prompt_segment(home: Utf8PathBuf) -> Result<Option<PromptSegment>> {
+    let penv = Penv::new(home)?;
+    let effective_environment = match current_dir().and_then(|dir| penv.effective_environment(&dir))
+    {
+        Ok(effective_environment) => effective_environment,
+        Err(e) => {
+            tracing::debug!("ignoring project file: {}", e);
+            penv.active_environment
+                .clone()
+                .map(|environment| (environment, EnvironmentSource::Global))
+        }
+    };
+
+    Ok(effective_environment.map(|(environment, source)| {
+        let metadata = environment.metadata();
+        let (pinned_version, checkout_dir) = match environment.as_ref() {
+            Environment::BinaryEnvironment(env) => (Some(env.pinned_version.to_string()), None),
+            Environment::CheckoutEnvironment(env) => (None, Some(env.checkout_dir())),
+        };
+
+        PromptSegment {
+            alias: metadata.alias.clone(),
+            pinned_version,
+            checkout_dir,
+            network: metadata.network(),
+            client_only: metadata.client_only,
+            source: source.kind().to_string(),
+        }
+    }))
+}
+
+/// The short id of the commit checked out in `checkout_dir`.
+fn checkout_commit(checkout_dir: &Utf8Path) -> Option<String> {
+    // Isolated, so that no git configuration outside the repository is read.
+    let repo = gix::open_opts(checkout_dir, gix::open::Options::isolated()).ok()?;
+    let id = repo.head_id().ok()?;
+
+    Some(id.shorten_or_id().to_string())
+}
@@ -0,0 +1,71 @@
+use std::{fs, io, path};
+
+use anyhow::{anyhow, Context as _, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::penv::Penv;
+
+#[derive(Debug, clap::Parser)]
+pub struct RelocateCmd {
+    /// The directory to move the penv home to, which must not exist yet or be empty.
+    ///
+    /// If the home was already moved, for example by restoring it from a backup, pass
+    /// its current location to update the environments without moving anything.
+    new_home: Utf8PathBuf,
+}
+
+impl RelocateCmd {
+    pub async fn exec(&self, home: Utf8PathBuf) -> Result<()> {
+        let home = absolute(&home)?;
+        let new_home = absolute(&self.new_home)?;
+
+        if new_home != home {
+            if new_home.starts_with(&home) {
+                return Err(anyhow!("cannot move {} into itself", home));
+            }
+            if new_home.exists() {
+                let is_empty = new_home.is_dir() && new_home.read_dir()?.next().is_none();
+                if !is_empty {
+                    return Err(anyhow!("{} already exists and is not empty", new_home));
+                }
+                fs::remove_dir(&new_home)?;
+            }
+            if let Some(parent) = new_home.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory {}", parent))?;
+            }
+
+            fs::rename(&home, &new_home).map_err(|e| match e.kind() {
+                io::ErrorKind::CrossesDevices => anyhow!(e).context(format!(
+                    "Failed to move {} to {}, which is on another filesystem; copy it with `cp -a` and run `penv --home {} relocate {}` instead",
+                    home, new_home, new_home, new_home
+                )),
+                _ => anyhow!(e).context(format!("Failed to move {} to {}", home, new_home)),
+            })?;
+        }
+
+        let mut penv = Penv::new(new_home.clone())?;
+        let previous_home = penv.previous_home.clone();
+        penv.finish_relocation()?;
+
+        match previous_home {
+            Some(previous_home) => {
+                println!("relocated penv home from {} to {}", previous_home, new_home)
+            }
+            None => println!("penv home {} is up to date", new_home),
+        }
+        if new_home != home {
+            println!(
+                "set PENUMBRA_PENV_HOME={} or pass --home {} to keep using it",
+                new_home, new_home
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn absolute(path: &Utf8Path) -> Result<Utf8PathBuf> {
+    let absolute = path::absolute(path).with_context(|| format!("Invalid path {}", path))?;
+    Utf8PathBuf::from_path_buf(absolute).map_err(|path| anyhow!("{} is not UTF-8", path.display()))
+}
@@ -0,0 +1,81 @@
+use std::io::IsTerminal as _;
+
+use anyhow::{anyhow, Result};
+use camino::Utf8PathBuf;
+
+use crate::penv::project::SHELL_ENVIRONMENT_VAR;
+use crate::penv::Penv;
+
+use super::hook::Shell;
+
+#[derive(Debug, clap::Parser)]
+pub struct ShellCmd {
+    /// The alias of the Penumbra environment to be activated in the current shell.
+    #[clap(required_unless_present = "unset")]
+    environment_alias: Option<String>,
+    /// Stop overriding the environment in the current shell, falling back to the
+    /// project or globally active environment.
+    #[clap(long, conflicts_with = "environment-alias")]
+    unset: bool,
+    /// Which shell to output the activation script for. Defaults to the `shell` setting.
+    #[clap(long, value_enum)]
+    shell: Option<Shell>,
+}
+
+impl ShellCmd {
+    pub async fn exec(&self, home: Utf8PathBuf) -> Result<()> {
+        let environment_alias = match &self.environment_alias {
+            Some(environment_alias) => {
+                let penv = Penv::new(home.clone())?;
+                if penv
+                    .environments
+                    .get_environment(environment_alias)
+                    .is_none()
+                {
+                    return Err(anyhow!(
+                        "Environment with alias {} does not exist",
+                        environment_alias
+                    ));
+                }
+                Some(environment_alias)
+            }
+            None => None,
+        };
+
+        // The output has to be evaluated by the shell itself, which the `penv` function
+        // installed by the hook takes care of.
+        if std::io::stdout().is_terminal() {
+            eprintln!("`penv shell` requires the penv shell hook, see `penv hook --help`");
+        }
+
+        let shell = Shell::or_configured(&self.shell, &home)?;
+        match shell {
+            Shell::Bash | Shell::Zsh => match environment_alias {
+                Some(environment_alias) => println!(
+                    "export {}={}",
+                    SHELL_ENVIRONMENT_VAR,
+                    shell.quote(environment_alias)
+                ),
+                None => println!("unset {}", SHELL_ENVIRONMENT_VAR),
+            },
+            Shell::Fish => match environment_alias {
+                Some(environment_alias) => println!(
+                    "set -gx {} {}",
+                    SHELL_ENVIRONMENT_VAR,
+                    shell.quote(environment_alias)
+                ),
+                None => println!("set -e {}", SHELL_ENVIRONMENT_VAR),
+            },
+            // nushell can't evaluate generated code, so the `penv` function installed by
+            // the hook sets the variable to the printed alias, or hides it if empty.
+            Shell::Nu => println!("{}", environment_alias.map_or("", |alias| alias.as_str())),
+            Shell::Unsupported => {
+                return Err(anyhow!(
+                    "please provide a supported shell: `zsh`, `bash`, `fish` or `nu`"
+                ))
+            }
+        }
+
+        Ok(())
+    }
+}
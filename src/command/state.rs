@@ -0,0 +1,176 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use colored::Colorize;
+
+use crate::penv::cache::cache::CacheData;
+use crate::penv::environment::{Environment, EnvironmentTrait as _, ManagedFile as _};
+use crate::penv::release::{InstalledRelease, RepoOrVersion};
+use crate::penv::state::StateFile;
+use crate::penv::Penv;
+
+#[derive(Debug, clap::Parser)]
+pub struct StateCmd {
+    #[clap(subcommand)]
+    pub subcmd: StateTopSubCmd,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum StateTopSubCmd {
+    /// Validate penv's state files, reporting any problems with them.
+    ///
+    /// Exits non-zero if any problems are found. Nothing is changed, so files written
+    /// with an older schema are only checked as they would be after migrating them.
+    #[clap(display_order = 100)]
+    Check,
+}
+
+impl StateCmd {
+    pub async fn exec(&self, home: Utf8PathBuf) -> Result<()> {
+        match self.subcmd {
+            StateTopSubCmd::Check => {
+                let problems = check(&home);
+                if problems.is_empty() {
+                    println!("{}", "no problems found".green());
+                    return Ok(());
+                }
+
+                for problem in &problems {
+                    println!("{}", problem.red());
+                }
+                Err(anyhow!("found {} problem(s) in {}", problems.len(), home))
+            }
+        }
+    }
+}
+
+/// Validates the state files in `home`, returning a description of each problem found.
+fn check(home: &Utf8Path) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let mut penv_contents = None;
+    let mut cache_data = CacheData::default();
+    for file in StateFile::ALL {
+        match file.read(home) {
+            Ok(Some(contents)) => {
+                if contents.version < file.schema_version() {
+                    println!(
+                        "{}: schema version {}, will be migrated to version {} when penv next loads it",
+                        file,
+                        contents.version,
+                        file.schema_version()
+                    );
+                } else {
+                    println!("{}: schema version {}", file, contents.version);
+                }
+
+                let parsed = match file {
+                    StateFile::Penv => contents.table.clone().try_into::<Penv>().map(|penv| {
+                        penv_contents = Some((penv, contents.table));
+                    }),
+                    StateFile::Cache => contents.table.try_into::<CacheData>().map(|data| {
+                        check_installed_releases(file, &data, &mut problems);
+                        cache_data = data;
+                    }),
+                };
+                if let Err(e) = parsed {
+                    problems.push(format!("{} is invalid: {}", file, e.to_string().trim_end()));
+                }
+            }
+            Ok(None) => println!("{}: not present", file),
+            Err(e) => problems.push(format!("{:#}", e)),
+        }
+    }
+
+    if let Some((penv, table)) = penv_contents {
+        let file = StateFile::Penv;
+        if penv.home_dir != home {
+            problems.push(format!(
+                "{}: the home was moved from {}; run `penv relocate {}` to update the environments",
+                file, penv.home_dir, home
+            ));
+        }
+
+        if let Some(alias) = table.get("active_environment").and_then(|v| v.as_str()) {
+            if penv.environments.get_environment(alias).is_none() {
+                problems.push(format!(
+                    "{}: the active environment {} does not exist",
+                    file, alias
+                ));
+            }
+        }
+
+        let mut aliases = HashSet::new();
+        for environment in penv.environments.iter() {
+            let alias = &environment.metadata().alias;
+            if !aliases.insert(alias) {
+                problems.push(format!(
+                    "{}: there are several environments with alias {}",
+                    file, alias
+                ));
+            }
+
+            if !environment.path().is_dir() {
+                problems.push(format!(
+                    "{}: the directory {} of environment {} does not exist",
+                    file,
+                    environment.path(),
+                    alias
+                ));
+            }
+
+            match environment.as_ref() {
+                Environment::BinaryEnvironment(env) => {
+                    let pinned = RepoOrVersion::Version(env.pinned_version.clone());
+                    if !cache_data
+                        .installed_releases
+                        .iter()
+                        .any(|release| release.matches(&env.repository_name, &pinned))
+                    {
+                        problems.push(format!(
+                            "{}: environment {} is pinned to {} from {}, which is not installed",
+                            file, alias, env.pinned_version, env.repository_name
+                        ));
+                    }
+                }
+                Environment::CheckoutEnvironment(env) => {
+                    if !env.checkout_dir().is_dir() {
+                        problems.push(format!(
+                            "{}: the git checkout {} of environment {} does not exist",
+                            file,
+                            env.checkout_dir(),
+                            alias
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+fn check_installed_releases(file: StateFile, data: &CacheData, problems: &mut Vec<String>) {
+    for installed_release in &data.installed_releases {
+        let missing = match installed_release {
+            InstalledRelease::Binary(release) => release
+                .assets
+                .iter()
+                .map(|asset| &asset.local_filepath)
+                .filter(|path| !path.is_file())
+                .collect::<Vec<_>>(),
+            InstalledRelease::GitCheckout(checkout) => Some(&checkout.install_path)
+                .filter(|path| !path.is_dir())
+                .into_iter()
+                .collect(),
+        };
+
+        for path in missing {
+            problems.push(format!(
+                "{}: {} of installed release {} does not exist",
+                file, path, installed_release
+            ));
+        }
+    }
+}
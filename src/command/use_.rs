@@ -1,6 +1,8 @@
 use anyhow::Result;
 use camino::Utf8PathBuf;
 
+use crate::penv::environment::EnvironmentTrait as _;
+use crate::penv::project::{current_dir, EnvironmentSource};
 use crate::penv::Penv;
 
 #[derive(Debug, clap::Parser)]
@@ -21,6 +23,22 @@ impl UseCmd {
 
         println!("activated");
 
+        // The global environment is shadowed by shell-scoped and project environments,
+        // so point that out rather than leaving the user wondering why nothing changed.
+        if let Ok(Some((environment, source))) =
+            current_dir().and_then(|dir| penv.effective_environment(&dir))
+        {
+            if source != EnvironmentSource::Global
+                && environment.metadata().alias != *environment_alias
+            {
+                println!(
+                    "note: environment {} ({}) takes precedence in this shell",
+                    environment.metadata().alias,
+                    source
+                );
+            }
+        }
+
         Ok(())
     }
 }
@@ -1,7 +1,12 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 use camino::Utf8PathBuf;
+use serde::Serialize;
 
-use crate::penv::environment::EnvironmentTrait;
+use super::OutputFormat;
+use crate::penv::environment::{Environment, EnvironmentTrait};
+use crate::penv::project::{current_dir, EnvironmentSource};
 use crate::penv::Penv;
 
 #[derive(Debug, clap::Parser)]
@@ -11,25 +16,101 @@ pub struct WhichCmd {
     detailed: bool,
 }
 
+/// The output of `penv which`.
+#[derive(Debug, Serialize)]
+struct EffectiveEnvironment {
+    environment: Option<Arc<Environment>>,
+    /// Why the environment is in effect, one of `shell`, `project` and `global`, or
+    /// `none` if no environment is in effect.
+    ///
+    /// Unlike the other fields it's always set, so that the TOML output, which leaves
+    /// out unset fields, isn't empty.
+    source: &'static str,
+    /// The project file selecting the environment, if the source is `project`.
+    project_file: Option<Utf8PathBuf>,
+}
+
+impl EffectiveEnvironment {
+    fn new(effective_environment: Option<&(Arc<Environment>, EnvironmentSource)>) -> Self {
+        match effective_environment {
+            Some((environment, source)) => EffectiveEnvironment {
+                environment: Some(environment.clone()),
+                source: source.kind(),
+                project_file: match source {
+                    EnvironmentSource::ProjectFile(path) => Some(path.clone()),
+                    _ => None,
+                },
+            },
+            None => EffectiveEnvironment {
+                environment: None,
+                source: "none",
+                project_file: None,
+            },
+        }
+    }
+}
+
 impl WhichCmd {
-    pub async fn exec(&self, home: Utf8PathBuf) -> Result<()> {
+    pub async fn exec(&self, home: Utf8PathBuf, output: OutputFormat) -> Result<()> {
         let detailed = &self.detailed;
         let penv = Penv::new(home.clone())?;
-        let active_environment = penv.active_environment.clone();
-
-        match active_environment {
-            Some(env) => {
-                if *detailed {
-                    println!("{}", env);
-                } else {
-                    println!("{}", env.metadata().alias);
+        let effective_environment = penv.effective_environment(&current_dir()?)?;
+
+        let effective = EffectiveEnvironment::new(effective_environment.as_ref());
+        output.print(&effective, || {
+            match effective_environment {
+                Some((env, source)) => {
+                    if *detailed {
+                        print!("{}", env);
+                        println!("Source: {}", source);
+                    } else {
+                        println!("{} ({})", env.metadata().alias, source);
+                    }
+                }
+                None => {
+                    println!("no active environment set");
                 }
             }
-            None => {
-                println!("no active environment set");
-            }
-        }
 
-        Ok(())
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8Path;
+    use serde_json::json;
+
+    use super::*;
+    use crate::penv::penv::tests::test_penv;
+
+    #[test]
+    fn output_layout() {
+        let none = EffectiveEnvironment::new(None);
+        assert_eq!(
+            serde_json::to_value(&none).unwrap(),
+            json!({ "environment": null, "source": "none", "project_file": null })
+        );
+        assert_eq!(toml::to_string(&none).unwrap(), "source = \"none\"\n");
+
+        let home = tempfile::tempdir().unwrap();
+        let home = Utf8Path::from_path(home.path()).unwrap();
+        let penv = test_penv(home);
+        let environment = penv.environments.get_environment("checkout").unwrap();
+        let project_file = home.join("project/.penv.toml");
+        let effective = EffectiveEnvironment::new(Some(&(
+            environment,
+            EnvironmentSource::ProjectFile(project_file.clone()),
+        )));
+        let value = serde_json::to_value(&effective).unwrap();
+        assert_eq!(value["source"], "project");
+        assert_eq!(value["project_file"], project_file.as_str());
+        assert_eq!(value["environment"]["type"], "CheckoutEnvironment");
+        assert_eq!(
+            value["environment"]["args"]["metadata"]["alias"],
+            "checkout"
+        );
+        assert_eq!(value.as_object().unwrap().len(), 3);
     }
 }
@@ -8,6 +8,7 @@ pub mod command;
 pub mod opt;
 mod penv;
 
+pub use penv::state::HomeLock;
 pub use penv::Penv;
 
 pub fn default_home() -> Utf8PathBuf {
@@ -5,8 +5,8 @@ use std::fs;
 use anyhow::{Context as _, Result};
 use clap::Parser;
 
-use penv::Penv;
 use penv::{command::Command, opt::Opt};
+use penv::{HomeLock, Penv};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -18,17 +18,33 @@ async fn main() -> Result<()> {
     fs::create_dir_all(&opt.home)
         .with_context(|| format!("Failed to create home directory {}", opt.home))?;
 
+    // Commands that change the state wait for each other, while read-only commands,
+    // such as those run by the shell hooks, never block.
+    let _lock = match opt.cmd.modifies_state() {
+        true => Some(HomeLock::acquire(&opt.home)?),
+        false => None,
+    };
+
     let cmd = &opt.cmd;
     match cmd {
         Command::Install(install_cmd) => install_cmd.exec(opt.home).await?,
-        Command::Cache(cache_cmd) => cache_cmd.exec(opt.home).await?,
+        Command::Cache(cache_cmd) => cache_cmd.exec(opt.home, opt.output).await?,
+        Command::State(state_cmd) => state_cmd.exec(opt.home).await?,
+        Command::Config(config_cmd) => config_cmd.exec(opt.home, opt.output).await?,
+        Command::Relocate(relocate_cmd) => relocate_cmd.exec(opt.home).await?,
+        Command::Doctor(doctor_cmd) => doctor_cmd.exec(opt.home).await?,
         Command::Manage(manage_cmd) => {
-            manage_cmd.exec(opt.home).await?;
+            manage_cmd.exec(opt.home, opt.output).await?;
         }
         Command::Use(use_cmd) => use_cmd.exec(opt.home).await?,
+        Command::Shell(shell_cmd) => shell_cmd.exec(opt.home).await?,
         Command::Hook(hook_cmd) => hook_cmd.exec(opt.home).await?,
+        Command::Completions(completions_cmd) => completions_cmd.exec(opt.home).await?,
         Command::Env(env_cmd) => env_cmd.exec(opt.home).await?,
-        Command::Which(which_cmd) => which_cmd.exec(opt.home).await?,
+        Command::Which(which_cmd) => which_cmd.exec(opt.home, opt.output).await?,
+        Command::Exec(exec_cmd) => exec_cmd.exec(opt.home).await?,
+        Command::Outdated(outdated_cmd) => outdated_cmd.exec(opt.home, opt.output).await?,
+        Command::Prompt(prompt_cmd) => prompt_cmd.exec(opt.home).await?,
         Command::UnsafeResetAll => {
             // rm the home directory
             println!("removing directory {}", opt.home);
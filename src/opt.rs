@@ -5,7 +5,7 @@ use std::io::IsTerminal as _;
 use tracing_subscriber::EnvFilter;
 use url::Url;
 
-use crate::command::Command;
+use crate::command::{Command, OutputFormat};
 
 #[derive(Debug, Parser)]
 #[clap(
@@ -24,6 +24,11 @@ pub struct Opt {
     /// By default, this URL is provided by pcli's config. See `pcli init` for more information.
     #[clap(long, parse(try_from_str = Url::parse))]
     pub grpc_url: Option<Url>,
+    /// The format to print listings and information in.
+    ///
+    /// "json" and "toml" follow stable schemas, for use in scripts.
+    #[clap(long, global = true, value_enum, default_value = "table")]
+    pub output: OutputFormat,
 }
 
 impl Opt {
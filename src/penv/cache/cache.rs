@@ -1,7 +1,4 @@
-use std::{
-    fs::{self, File},
-    io::Write as _,
-};
+use std::fs;
 
 use anyhow::{anyhow, Context, Result};
 use camino::Utf8PathBuf;
@@ -11,9 +8,10 @@ use sha2::{Digest as _, Sha256};
 use crate::penv::{
     downloader::Downloader,
     release::{
-        Installable as _, InstallableRelease, InstalledRelease, Release, RepoOrVersion,
-        RepoOrVersionReq, UsableRelease as _, VersionReqOrLatest,
+        Installable as _, InstallableRelease, InstalledRelease, Release, ReleaseChannel,
+        RepoOrVersion, RepoOrVersionReq, UsableRelease as _, VersionReqOrLatest,
     },
+    state::{write_atomic, StateFile},
 };
 
 /// The Cache is responsible for maintaining a directory of all installed software versions.
@@ -24,7 +22,7 @@ pub struct Cache {
 }
 
 /// Data to persist regarding a cache instance.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct CacheData {
     pub installed_releases: Vec<InstalledRelease>,
 }
@@ -32,17 +30,9 @@ pub struct CacheData {
 impl Cache {
     pub fn new(home: Utf8PathBuf) -> Result<Self> {
         // read config file to fetch installed releases
-        let config_path = home.join("cache.toml");
-        let metadata = fs::metadata(&config_path);
-
-        let data = if metadata.is_err() || !metadata.unwrap().is_file() {
-            CacheData {
-                installed_releases: Vec::new(),
-            }
-        } else {
-            let config_contents = fs::read_to_string(config_path)?;
-            toml::from_str(&config_contents)?
-        };
+        let data = StateFile::Cache.load(&home)?.unwrap_or_else(|| CacheData {
+            installed_releases: Vec::new(),
+        });
 
         Ok(Self { home, data })
     }
@@ -59,10 +49,19 @@ impl Cache {
     }
 
     /// Find the best matching installed release for a given version/git repo requirement.
-    pub fn find_best_match(&self, required: &RepoOrVersionReq) -> Option<&InstalledRelease> {
+    ///
+    /// Version requirements only match binary releases from the GitHub repository
+    /// `repository_name`. A `latest` requirement resolves to the newest installed release
+    /// on the given channel.
+    pub fn find_best_match(
+        &self,
+        repository_name: &str,
+        required: &RepoOrVersionReq,
+        channel: ReleaseChannel,
+    ) -> Option<&InstalledRelease> {
         // TODO: don't unwrap_or_else here
         let matching_versions = self
-            .list_installed(Some(required))
+            .list_installed(Some(repository_name), Some(required), channel)
             .unwrap_or_else(|_| vec![]);
 
         // `InstalledRelease` can't be ordered because there is no meaningful ordering between
@@ -103,8 +102,11 @@ impl Cache {
     // manage its installation directories
     fn generate_installed_release_path(&self, release: &InstallableRelease) -> Utf8PathBuf {
         match release {
+            // Releases are installed to `versions/<owner>/<repo>/<version>`, so that equal
+            // versions from different repositories don't collide.
             InstallableRelease::Binary(release) => {
                 let mut path = self.home.join("versions");
+                path.push(&release.repository_name);
                 path.push(release.version().to_string());
 
                 path
@@ -123,20 +125,28 @@ impl Cache {
         }
     }
 
+    /// Finds the installed release of a version from the GitHub repository
+    /// `repository_name`, or the checkout of a git repo.
     pub fn get_installed_release(
         &self,
+        repository_name: &str,
         repo_or_version: &RepoOrVersion,
     ) -> Option<&InstalledRelease> {
         self.data
             .installed_releases
             .iter()
-            .find(|r| r.matches(repo_or_version))
+            .find(|r| r.matches(repository_name, repo_or_version))
     }
 
     /// For a binary release with a pinned version, finds the pcli binary for the given version.
     // TODO: maybe move to BinaryRelease and take a Cache ref or something
-    pub fn get_pcli_for_version(&self, version: &semver::Version) -> Option<&Utf8PathBuf> {
-        let release = self.get_installed_release(&RepoOrVersion::Version(version.clone()))?;
+    pub fn get_pcli_for_version(
+        &self,
+        repository_name: &str,
+        version: &semver::Version,
+    ) -> Option<&Utf8PathBuf> {
+        let release =
+            self.get_installed_release(repository_name, &RepoOrVersion::Version(version.clone()))?;
 
         release.assets().iter().find_map(|a| {
             if a.local_filepath.file_name().unwrap() == "pcli" {
@@ -147,8 +157,13 @@ impl Cache {
         })
     }
 
-    pub fn get_pclientd_for_version(&self, version: &semver::Version) -> Option<&Utf8PathBuf> {
-        let release = self.get_installed_release(&RepoOrVersion::Version(version.clone()))?;
+    pub fn get_pclientd_for_version(
+        &self,
+        repository_name: &str,
+        version: &semver::Version,
+    ) -> Option<&Utf8PathBuf> {
+        let release =
+            self.get_installed_release(repository_name, &RepoOrVersion::Version(version.clone()))?;
 
         release.assets().iter().find_map(|a| {
             if a.local_filepath.file_name().unwrap() == "pclientd" {
@@ -159,8 +174,13 @@ impl Cache {
         })
     }
 
-    pub fn get_pd_for_version(&self, version: &semver::Version) -> Option<&Utf8PathBuf> {
-        let release = self.get_installed_release(&RepoOrVersion::Version(version.clone()))?;
+    pub fn get_pd_for_version(
+        &self,
+        repository_name: &str,
+        version: &semver::Version,
+    ) -> Option<&Utf8PathBuf> {
+        let release =
+            self.get_installed_release(repository_name, &RepoOrVersion::Version(version.clone()))?;
 
         release.assets().iter().find_map(|a| {
             if a.local_filepath.file_name().unwrap() == "pd" {
@@ -176,11 +196,10 @@ impl Cache {
         fs::create_dir_all(&self.home)
             .with_context(|| format!("Failed to create home directory {}", self.home))?;
 
-        let toml_cache = toml::to_string(&self.data)?;
+        let toml_cache = StateFile::Cache.serialize_contents(&self.home, &self.data)?;
 
         tracing::debug!(config_file_path=?self.config_file_path(),"create file");
-        let mut file = File::create(self.config_file_path())?;
-        file.write_all(toml_cache.as_bytes())?;
+        write_atomic(&self.config_file_path(), &toml_cache)?;
 
         Ok(())
     }
@@ -192,29 +211,33 @@ impl Cache {
     /// Returns all versions available from the upstream repository,
     /// and whether they're installed, optionally matching a given semver version requirement.
     ///
-    /// Only relevant for binary releases right now.
+    /// Only relevant for binary releases right now. Without a requirement, only releases on the
+    /// given channel are returned, and a `latest` requirement resolves to the newest of them.
+    /// Explicit version requirements follow semver's prerelease matching rules.
     pub(crate) async fn list_available(
         &self,
         required_version: Option<&RepoOrVersionReq>,
+        channel: ReleaseChannel,
         downloader: &Downloader,
     ) -> Result<Vec<(Release, bool)>> {
-        // TODO: fetching of available releases should be cached in the downloader,
-        // currently it happens multiple times
         let mut available_releases = downloader.fetch_releases().await?;
 
-        let latest_version = available_releases
-            .iter()
-            .max()
-            .ok_or_else(|| anyhow!("No releases found"))?
+        let latest_version = channel
+            .latest_release(&available_releases)
+            .ok_or_else(|| anyhow!("No {} releases found", channel))?
             .version
             .clone();
 
         // Only retain the releases that match the version requirement
         available_releases.retain(|r| {
-            if let Some(required_version) = required_version {
-                required_version.matches(&r.version, &latest_version)
-            } else {
-                true
+            // Drafts are only ever visible on the `all` channel
+            if r.draft && channel != ReleaseChannel::All {
+                return false;
+            }
+
+            match required_version {
+                Some(required_version) => required_version.matches(&r.version, &latest_version),
+                None => channel.includes_release(r),
             }
         });
 
@@ -223,7 +246,10 @@ impl Cache {
             .into_iter()
             .map(|r| {
                 let installed = self
-                    .get_installed_release(&RepoOrVersion::Version(r.version.clone()))
+                    .get_installed_release(
+                        downloader.repository_name(),
+                        &RepoOrVersion::Version(r.version.clone()),
+                    )
                     .is_some();
                 (r, installed)
             })
@@ -232,12 +258,34 @@ impl Cache {
         Ok(return_releases)
     }
 
-    /// Returns all installed versions, optionally matching a given semver version requirement.
+    /// Returns all installed versions, optionally only those from the GitHub repository
+    /// `repository_name` and matching a given semver version requirement.
+    ///
+    /// A `latest` requirement matches the newest installed release on the given channel.
     pub fn list_installed(
         &self,
+        repository_name: Option<&str>,
         required_version: Option<&RepoOrVersionReq>,
+        channel: ReleaseChannel,
     ) -> Result<Vec<&InstalledRelease>> {
-        let mut releases = self.data.installed_releases.iter().collect::<Vec<_>>();
+        let mut releases = self
+            .data
+            .installed_releases
+            .iter()
+            .filter(|r| match (r, repository_name) {
+                (InstalledRelease::Binary(r), Some(repository_name)) => {
+                    r.repository_name == repository_name
+                }
+                _ => true,
+            })
+            .collect::<Vec<_>>();
+
+        let latest_version = channel
+            .latest(releases.iter().filter_map(|r| match r {
+                InstalledRelease::Binary(r) => Some(&r.version),
+                InstalledRelease::GitCheckout(_) => None,
+            }))
+            .cloned();
 
         if let Some(required_version) = required_version {
             releases.retain(|r| match (r, required_version) {
@@ -246,9 +294,7 @@ impl Cache {
                     InstalledRelease::Binary(r),
                     RepoOrVersionReq::VersionReqOrLatest(version_req),
                 ) => match version_req {
-                    VersionReqOrLatest::Latest => {
-                        unreachable!("can't search installed for 'latest'")
-                    }
+                    VersionReqOrLatest::Latest => latest_version.as_ref() == Some(&r.version),
                     VersionReqOrLatest::VersionReq(version_req) => version_req.matches(&r.version),
                 },
                 // Checkout release and repo requirement supplied -- matchable
@@ -283,6 +329,7 @@ mod tests {
     fn deserialize_cache() {
         let cache_data = CacheData {
             installed_releases: vec![InstalledRelease::Binary(InstalledBinaryRelease {
+                repository_name: "penumbra-zone/penumbra".to_string(),
                 version: Version::parse("1.0.0").unwrap(),
                 body: Some("Release notes for version 1.0.0".to_string()),
                 assets: vec![InstalledAsset {
@@ -303,6 +350,7 @@ mod tests {
         type = "Binary"
 
         [installed_releases.args]
+        repository_name = "penumbra-zone/penumbra"
         version = "1.0.0"
         body = "Release notes for version 1.0.0"
         name = "Release 1.0.0"
@@ -316,4 +364,48 @@ mod tests {
         // Deserialize from TOML string
         toml::from_str::<CacheData>(toml_str).unwrap();
     }
+
+    #[test]
+    fn releases_keyed_by_repository() {
+        let release = |repository_name: &str, version: &str| {
+            InstalledRelease::Binary(InstalledBinaryRelease {
+                repository_name: repository_name.to_string(),
+                version: Version::parse(version).unwrap(),
+                body: None,
+                assets: vec![],
+                name: version.to_string(),
+                root_dir: Utf8PathBuf::from("/tmp/fake"),
+            })
+        };
+        let cache = Cache {
+            home: "/tmp/fake".into(),
+            data: CacheData {
+                installed_releases: vec![
+                    release("penumbra-zone/penumbra", "1.0.0"),
+                    release("example/fork", "1.0.0"),
+                    release("example/fork", "2.0.0"),
+                ],
+            },
+        };
+
+        let version = RepoOrVersion::Version(Version::new(2, 0, 0));
+        assert!(cache
+            .get_installed_release("penumbra-zone/penumbra", &version)
+            .is_none());
+        assert!(cache
+            .get_installed_release("example/fork", &version)
+            .is_some());
+
+        // `latest` resolves within the repository.
+        let latest = RepoOrVersionReq::VersionReqOrLatest(VersionReqOrLatest::Latest);
+        let best_match = cache
+            .find_best_match("penumbra-zone/penumbra", &latest, ReleaseChannel::Stable)
+            .unwrap();
+        assert_eq!(best_match, &release("penumbra-zone/penumbra", "1.0.0"));
+
+        let all = cache
+            .list_installed(None, None, ReleaseChannel::Stable)
+            .unwrap();
+        assert_eq!(all.len(), 3);
+    }
 }
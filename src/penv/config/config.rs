@@ -0,0 +1,395 @@
+use std::fmt::{self, Display};
+use std::{env, fs, io};
+
+use anyhow::{anyhow, Context as _, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use toml::Table;
+
+use crate::command::Shell;
+use crate::penv::release::{ReleaseChannel, DEFAULT_REPOSITORY_NAME};
+use crate::penv::state::write_atomic;
+
+/// The port of the CometBFT RPC endpoint, used to derive the URL for `pd network join`
+/// from the GRPC URL unless configured otherwise.
+pub const DEFAULT_JOIN_PORT: u16 = 26657;
+
+/// The address pd advertises to its peers unless configured otherwise.
+pub const DEFAULT_EXTERNAL_ADDRESS: &str = "0.0.0.0:26656";
+
+/// A setting in `config.toml`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, clap::ValueEnum)]
+#[clap(rename_all = "snake_case")]
+pub enum ConfigKey {
+    /// The GitHub repository to install releases from.
+    RepositoryName,
+    /// The port of the CometBFT RPC endpoint that `penv manage create` joins the network
+    /// through, when no `--pd-join-url` is given.
+    JoinPort,
+    /// The address pd advertises to its peers, as `IP:PORT`.
+    ExternalAddress,
+    /// The shell to output scripts for, when none is given.
+    Shell,
+    /// The release channel a "latest" version requirement resolves against.
+    Channel,
+    /// A GitHub token to authenticate requests to the GitHub API with, for example to
+    /// avoid its rate limits.
+    GithubToken,
+}
+
+impl ConfigKey {
+    pub const ALL: [ConfigKey; 6] = [
+        ConfigKey::RepositoryName,
+        ConfigKey::JoinPort,
+        ConfigKey::ExternalAddress,
+        ConfigKey::Shell,
+        ConfigKey::Channel,
+        ConfigKey::GithubToken,
+    ];
+
+    /// The name of the setting in `config.toml`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigKey::RepositoryName => "repository_name",
+            ConfigKey::JoinPort => "join_port",
+            ConfigKey::ExternalAddress => "external_address",
+            ConfigKey::Shell => "shell",
+            ConfigKey::Channel => "channel",
+            ConfigKey::GithubToken => "github_token",
+        }
+    }
+
+    /// The environment variable overriding the setting, e.g. `PENUMBRA_PENV_CHANNEL`.
+    pub fn env_var(&self) -> String {
+        format!("PENUMBRA_PENV_{}", self.name().to_uppercase())
+    }
+
+    /// Whether the value is a secret, which is not shown unless asked for.
+    pub fn is_secret(&self) -> bool {
+        matches!(self, ConfigKey::GithubToken)
+    }
+
+    /// Converts a value given on the command line or in an environment variable to
+    /// the type stored in `config.toml`.
+    fn parse(&self, value: &str) -> Result<toml::Value> {
+        match self {
+            ConfigKey::JoinPort => value
+                .parse::<u16>()
+                .map(|port| toml::Value::Integer(port.into()))
+                .map_err(|e| anyhow!("invalid port {}: {}", value, e)),
+            _ => Ok(toml::Value::String(value.to_string())),
+        }
+    }
+}
+
+impl Display for ConfigKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// Where the effective value of a setting comes from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigSource {
+    Default,
+    File,
+    Environment,
+}
+
+impl Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = match self {
+            ConfigSource::Default => "default",
+            ConfigSource::File => "config file",
+            ConfigSource::Environment => "environment",
+        };
+        write!(f, "{}", source)
+    }
+}
+
+/// The settings from a single source, each of which may be unset.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub join_port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell: Option<Shell>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<ReleaseChannel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub github_token: Option<String>,
+}
+
+impl Settings {
+    /// Returns the settings with `key` set to `value`, or unset if `value` is `None`.
+    ///
+    /// The value is validated by deserializing the updated settings.
+    fn with(&self, key: ConfigKey, value: Option<toml::Value>) -> Result<Self> {
+        let mut table = self.to_table()?;
+        match value {
+            Some(value) => table.insert(key.name().to_string(), value),
+            None => table.remove(key.name()),
+        };
+
+        table
+            .try_into()
+            .map_err(|e: toml::de::Error| anyhow!("invalid {}: {}", key, e.message()))
+    }
+
+    /// The value of `key`, formatted as given to `penv config set`.
+    fn get(&self, key: ConfigKey) -> Result<Option<String>> {
+        Ok(self.to_table()?.get(key.name()).map(|value| match value {
+            toml::Value::String(value) => value.clone(),
+            value => value.to_string(),
+        }))
+    }
+
+    fn to_table(&self) -> Result<Table> {
+        Ok(Table::try_from(self)?)
+    }
+}
+
+/// The user's configuration, providing the defaults for command-line flags.
+///
+/// Each setting is taken from its `PENUMBRA_PENV_*` environment variable, then from
+/// `config.toml` in the penv home, and finally from penv's built-in default. Flags given
+/// on the command line take precedence over all of them.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub home: Utf8PathBuf,
+    /// The settings in `config.toml`.
+    pub file: Settings,
+    /// The settings overridden by environment variables.
+    pub environment: Settings,
+}
+
+impl Config {
+    pub fn load(home: &Utf8Path) -> Result<Self> {
+        let path = Self::path(home);
+        let file = match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| {
+                anyhow!(
+                    "{} is invalid: {}\nfix it with `penv config set` or `penv config unset`, or by editing the file",
+                    path,
+                    e.to_string().trim_end()
+                )
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Settings::default(),
+            Err(e) => return Err(anyhow!(e).context(format!("Failed to read {}", path))),
+        };
+
+        let mut environment = Settings::default();
+        for key in ConfigKey::ALL {
+            if let Ok(value) = env::var(key.env_var()) {
+                environment = key
+                    .parse(&value)
+                    .and_then(|value| environment.with(key, Some(value)))
+                    .with_context(|| format!("{} is invalid", key.env_var()))?;
+            }
+        }
+
+        Ok(Self {
+            home: home.to_path_buf(),
+            file,
+            environment,
+        })
+    }
+
+    /// The path of the configuration file in the penv home.
+    pub fn path(home: &Utf8Path) -> Utf8PathBuf {
+        home.join("config.toml")
+    }
+
+    /// The effective value of `key` and where it comes from, or `None` if it's unset
+    /// and has no default.
+    pub fn get(&self, key: ConfigKey) -> Result<Option<(String, ConfigSource)>> {
+        if let Some(value) = self.environment.get(key)? {
+            return Ok(Some((value, ConfigSource::Environment)));
+        }
+        if let Some(value) = self.file.get(key)? {
+            return Ok(Some((value, ConfigSource::File)));
+        }
+
+        let default = match key {
+            ConfigKey::RepositoryName => Some(DEFAULT_REPOSITORY_NAME.to_string()),
+            ConfigKey::JoinPort => Some(DEFAULT_JOIN_PORT.to_string()),
+            ConfigKey::ExternalAddress => Some(DEFAULT_EXTERNAL_ADDRESS.to_string()),
+            ConfigKey::Channel => Some(ReleaseChannel::default().to_string()),
+            ConfigKey::Shell | ConfigKey::GithubToken => None,
+        };
+        Ok(default.map(|value| (value, ConfigSource::Default)))
+    }
+
+    /// Sets `key` in `config.toml` in the penv home, or removes it if `value` is `None`.
+    ///
+    /// Only the new value is validated. The rest of the file and the environment
+    /// variables aren't, so that an invalid setting elsewhere can still be fixed.
+    pub fn set(home: &Utf8Path, key: ConfigKey, value: Option<&str>) -> Result<()> {
+        let value = value.map(|value| key.parse(value)).transpose()?;
+        if let Some(value) = &value {
+            Settings::default().with(key, Some(value.clone()))?;
+        }
+
+        Self::edit_file(home, key.name(), value)?;
+        Ok(())
+    }
+
+    /// Removes the setting `name` from `config.toml` in the penv home, including settings
+    /// penv doesn't know, such as misspelt ones.
+    ///
+    /// Returns whether the file had the setting.
+    pub fn unset(home: &Utf8Path, name: &str) -> Result<bool> {
+        Self::edit_file(home, name, None)
+    }
+
+    /// Sets or removes a setting in `config.toml`, leaving the others as they are.
+    ///
+    /// Returns whether the file had the setting before.
+    fn edit_file(home: &Utf8Path, name: &str, value: Option<toml::Value>) -> Result<bool> {
+        let path = Self::path(home);
+        let mut table = match fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .parse::<Table>()
+                .map_err(|e| anyhow!("{} is not valid TOML: {}", path, e.message()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Table::new(),
+            Err(e) => return Err(anyhow!(e).context(format!("Failed to read {}", path))),
+        };
+
+        let previous = match value {
+            Some(value) => table.insert(name.to_string(), value),
+            None => table.remove(name),
+        };
+        write_atomic(&path, &toml::to_string(&table)?)?;
+
+        Ok(previous.is_some())
+    }
+
+    pub fn repository_name(&self) -> String {
+        self.environment
+            .repository_name
+            .clone()
+            .or_else(|| self.file.repository_name.clone())
+            .unwrap_or_else(|| DEFAULT_REPOSITORY_NAME.to_string())
+    }
+
+    pub fn join_port(&self) -> u16 {
+        self.environment
+            .join_port
+            .or(self.file.join_port)
+            .unwrap_or(DEFAULT_JOIN_PORT)
+    }
+
+    pub fn external_address(&self) -> String {
+        self.environment
+            .external_address
+            .clone()
+            .or_else(|| self.file.external_address.clone())
+            .unwrap_or_else(|| DEFAULT_EXTERNAL_ADDRESS.to_string())
+    }
+
+    pub fn shell(&self) -> Option<Shell> {
+        self.environment
+            .shell
+            .clone()
+            .or_else(|| self.file.shell.clone())
+    }
+
+    pub fn channel(&self) -> ReleaseChannel {
+        self.environment
+            .channel
+            .or(self.file.channel)
+            .unwrap_or_default()
+    }
+
+    pub fn github_token(&self) -> Option<String> {
+        self.environment
+            .github_token
+            .clone()
+            .or_else(|| self.file.github_token.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layered_settings() {
+        let home = tempfile::tempdir().unwrap();
+        let home = Utf8Path::from_path(home.path()).unwrap();
+
+        // Without a config file, the built-in defaults apply.
+        let config = Config::load(home).unwrap();
+        assert_eq!(config.join_port(), DEFAULT_JOIN_PORT);
+        assert_eq!(
+            config.get(ConfigKey::JoinPort).unwrap(),
+            Some(("26657".to_string(), ConfigSource::Default))
+        );
+        assert_eq!(config.get(ConfigKey::GithubToken).unwrap(), None);
+
+        Config::set(home, ConfigKey::JoinPort, Some("36657")).unwrap();
+        Config::set(home, ConfigKey::Channel, Some("prerelease")).unwrap();
+        Config::set(home, ConfigKey::Shell, Some("zsh")).unwrap();
+        assert!(Config::set(home, ConfigKey::JoinPort, Some("not-a-port")).is_err());
+        assert!(Config::set(home, ConfigKey::Channel, Some("nightly")).is_err());
+
+        let mut config = Config::load(home).unwrap();
+        assert_eq!(config.join_port(), 36657);
+        assert_eq!(config.channel(), ReleaseChannel::Prerelease);
+        assert!(matches!(config.shell(), Some(Shell::Zsh)));
+        assert_eq!(
+            config.get(ConfigKey::Channel).unwrap(),
+            Some(("prerelease".to_string(), ConfigSource::File))
+        );
+
+        // Environment variables take precedence over the file. They're set on the
+        // loaded config, as other tests may run concurrently in this process.
+        config.environment = config
+            .environment
+            .with(ConfigKey::JoinPort, Some(toml::Value::Integer(46657)))
+            .unwrap();
+        assert_eq!(config.join_port(), 46657);
+        assert_eq!(
+            config.get(ConfigKey::JoinPort).unwrap(),
+            Some(("46657".to_string(), ConfigSource::Environment))
+        );
+
+        Config::set(home, ConfigKey::Channel, None).unwrap();
+        assert_eq!(
+            Config::load(home).unwrap().channel(),
+            ReleaseChannel::Stable
+        );
+
+        // Unknown settings are rejected rather than ignored.
+        fs::write(Config::path(home), "join_prot = 1\n").unwrap();
+        assert!(Config::load(home).is_err());
+    }
+
+    #[test]
+    fn repair_invalid_file() {
+        let home = tempfile::tempdir().unwrap();
+        let home = Utf8Path::from_path(home.path()).unwrap();
+        fs::write(
+            Config::path(home),
+            "join_prot = 1\nchannel = \"nightly\"\nshell = \"zsh\"\n",
+        )
+        .unwrap();
+        assert!(Config::load(home).is_err());
+
+        // Settings can be changed without the rest of the file being valid.
+        Config::set(home, ConfigKey::Channel, Some("prerelease")).unwrap();
+        assert!(Config::load(home).is_err());
+        assert!(Config::unset(home, "join_prot").unwrap());
+        assert!(!Config::unset(home, "join_prot").unwrap());
+
+        let config = Config::load(home).unwrap();
+        assert_eq!(config.channel(), ReleaseChannel::Prerelease);
+        assert!(matches!(config.shell(), Some(Shell::Zsh)));
+    }
+}
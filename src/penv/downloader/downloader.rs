@@ -3,44 +3,89 @@ use camino::Utf8PathBuf;
 use flate2::read::GzDecoder;
 use futures::stream::StreamExt;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, USER_AGENT};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT};
 use reqwest::Client;
 use sha2::{Digest, Sha256};
-use std::sync::Arc;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
 use tar::Archive;
 use tempfile::{tempdir, TempDir};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::OnceCell;
 use tokio::task;
 
-use crate::penv::release::{InstallableBinaryRelease, InstallableRelease, RawRelease, Release};
+use crate::penv::release::{
+    InstallableBinaryRelease, InstallableRelease, RawRelease, Release, TagPattern,
+};
+use crate::penv::snapshot::record_published_versions;
 
 /// Manages downloading files to a target directory and displaying progress.
+///
+/// The HTTP client and the temporary download directory are only created once they're
+/// first needed, so that commands that never download anything don't pay for them.
 #[derive(Debug, Clone)]
 pub(crate) struct Downloader {
-    client: Client,
-    temp_dir: Arc<TempDir>,
+    client: Arc<OnceLock<Client>>,
+    temp_dir: Arc<OnceLock<TempDir>>,
     repository_name: String,
+    /// Maps the repository's release tags to versions.
+    tag_pattern: TagPattern,
+    /// Authenticates requests to the GitHub API, if set.
+    github_token: Option<String>,
+    /// The releases fetched from the repository, so that they're only fetched once per run.
+    releases: Arc<OnceCell<Vec<Release>>>,
+    /// The penv home, where the fetched versions are recorded for shell completions.
+    home: Utf8PathBuf,
 }
 
 impl Downloader {
-    pub fn new(repository_name: String) -> Result<Self> {
-        // Create a temporary directory
-        let temp_dir = Arc::new(tempdir()?);
-
+    pub fn new(
+        repository_name: String,
+        tag_pattern: TagPattern,
+        github_token: Option<String>,
+        home: Utf8PathBuf,
+    ) -> Result<Self> {
         Ok(Self {
-            client: Client::new(),
-            temp_dir,
+            client: Arc::new(OnceLock::new()),
+            temp_dir: Arc::new(OnceLock::new()),
             repository_name,
+            tag_pattern,
+            github_token,
+            releases: Arc::new(OnceCell::new()),
+            home,
         })
     }
 
+    /// The GitHub repository releases are fetched from.
+    pub fn repository_name(&self) -> &str {
+        &self.repository_name
+    }
+
+    fn client(&self) -> &Client {
+        self.client.get_or_init(Client::new)
+    }
+
+    /// Returns the temporary directory downloads are extracted to, creating it on first use.
+    fn temp_dir(&self) -> Result<&Path> {
+        if self.temp_dir.get().is_none() {
+            // If another task created the directory in the meantime, ours is dropped
+            let _ = self.temp_dir.set(tempdir()?);
+        }
+
+        Ok(self
+            .temp_dir
+            .get()
+            .expect("temp dir was initialized above")
+            .path())
+    }
+
     pub async fn get_contents(&self, url: &str) -> Result<Vec<u8>> {
         println!("downloading shasum from {}", url);
 
         // Send the GET request and get the response
         // let response = self.client.get(url).send()?.error_for_status()?;
-        let response = self.client.get(url).send().await?.error_for_status()?;
+        let response = self.client().get(url).send().await?.error_for_status()?;
 
         // Read the response bytes into a Vec<u8>
         let content = response.bytes().await?.to_vec();
@@ -48,7 +93,17 @@ impl Downloader {
         Ok(content)
     }
 
+    /// Returns all releases available from the repository.
+    ///
+    /// The releases are fetched on first use and reused for the lifetime of the downloader.
     pub async fn fetch_releases(&self) -> Result<Vec<Release>> {
+        self.releases
+            .get_or_try_init(|| self.fetch_releases_uncached())
+            .await
+            .cloned()
+    }
+
+    async fn fetch_releases_uncached(&self) -> Result<Vec<Release>> {
         println!(
             "fetching available releases from https://api.github.com/repos/{}/releases",
             self.repository_name
@@ -68,9 +123,15 @@ impl Downloader {
             HeaderValue::from_static("2022-11-28"),
         );
         headers.insert(USER_AGENT, HeaderValue::from_static("curl/7.68.0"));
+        if let Some(github_token) = &self.github_token {
+            let mut authorization = HeaderValue::from_str(&format!("Bearer {}", github_token))
+                .map_err(|_| anyhow!("the GitHub token is not a valid header value"))?;
+            authorization.set_sensitive(true);
+            headers.insert(AUTHORIZATION, authorization);
+        }
 
         let response = self
-            .client
+            .client()
             .get(format!(
                 "https://api.github.com/repos/{repository_name}/releases"
             ))
@@ -85,11 +146,24 @@ impl Downloader {
             // Parse the JSON response into a Rust struct
             let releases: Vec<RawRelease> = response.json().await?;
 
-            // 3a. Enrich all the releases with proper domain types
+            // 3a. Enrich all the releases with proper domain types, skipping any whose tag
+            // doesn't match the tag pattern, so that repositories with mixed tags still work
             let enriched_releases: Vec<Release> = releases
                 .iter()
-                .map(|r| r.try_into())
-                .collect::<Result<_>>()?;
+                .filter_map(|r| match r.try_into_release(&self.tag_pattern) {
+                    Ok(release) => Some(release),
+                    Err(e) => {
+                        tracing::debug!("skipping release {}: {}", r.name, e);
+                        None
+                    }
+                })
+                .collect();
+
+            if let Err(e) =
+                record_published_versions(&self.home, &self.repository_name, &enriched_releases)
+            {
+                tracing::debug!("failed to record the published versions: {}", e);
+            }
 
             Ok(enriched_releases)
         } else {
@@ -116,7 +190,7 @@ impl Downloader {
             .ok_or_else(|| anyhow!("Failed to get file name from URL"))?;
 
         let response = self
-            .client
+            .client()
             .get(url.clone())
             .send()
             .await?
@@ -171,7 +245,7 @@ impl Downloader {
         for entry in archive.entries()? {
             let mut entry = entry?;
             let path = entry.path()?.to_path_buf();
-            let full_path = self.temp_dir.path().join(&path);
+            let full_path = self.temp_dir()?.join(&path);
 
             // Create parent directories if needed
             if let Some(parent) = full_path.parent() {
@@ -326,7 +400,7 @@ impl Downloader {
                         .ok_or_else(|| anyhow!("Failed to get file name from URL"))?
                         .to_string();
                     let temp_file_path =
-                        Utf8PathBuf::from_path_buf(self.temp_dir.path().join(&file_name))
+                        Utf8PathBuf::from_path_buf(self.temp_dir()?.join(&file_name))
                             .map_err(|_| anyhow!("Failed to create temp file path"))?;
                     let handle = tokio::spawn(async move {
                         let (file_name, downloaded_files) = arc_self
@@ -353,7 +427,7 @@ impl Downloader {
                         .ok_or_else(|| anyhow!("Failed to get file name from URL"))?
                         .to_string();
                     let temp_file_path =
-                        Utf8PathBuf::from_path_buf(self.temp_dir.path().join(&file_name))
+                        Utf8PathBuf::from_path_buf(self.temp_dir()?.join(&file_name))
                             .map_err(|_| anyhow!("Failed to create temp file path"))?;
                     let handle = tokio::spawn(async move {
                         let (file_name, downloaded_files) = arc_self
@@ -385,9 +459,8 @@ impl Downloader {
                     .next()
                     .ok_or_else(|| anyhow!("Failed to get file name from URL"))?
                     .to_string();
-                let temp_file_path =
-                    Utf8PathBuf::from_path_buf(self.temp_dir.path().join(&file_name))
-                        .map_err(|_| anyhow!("Failed to create temp file path"))?;
+                let temp_file_path = Utf8PathBuf::from_path_buf(self.temp_dir()?.join(&file_name))
+                    .map_err(|_| anyhow!("Failed to create temp file path"))?;
                 let handle = tokio::spawn(async move {
                     let (file_name, downloaded_files) = arc_self
                         .download_file(url, temp_file_path.clone(), progress_bar, pd_shasum)
@@ -439,6 +512,7 @@ impl Downloader {
         mp_thread.join().unwrap();
 
         Ok(InstallableRelease::Binary(InstallableBinaryRelease {
+            repository_name: self.repository_name.clone(),
             pcli,
             pclientd,
             pd,
@@ -13,10 +13,56 @@ mod pcli;
 mod pclientd;
 mod pd;
 
-use std::collections::HashMap;
+use std::{collections::HashMap, fs};
 
-use anyhow::Result;
-use camino::Utf8PathBuf;
+use anyhow::{anyhow, Context as _, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use url::Url;
+
+use crate::penv::state::write_atomic;
 pub(crate) use pcli::*;
 pub(crate) use pclientd::*;
 pub(crate) use pd::*;
+
+/// Points the config file at `config_path` at a new GRPC URL, leaving its other settings alone.
+fn rewrite_grpc_url(config_path: &Utf8Path, grpc_url: &Url) -> Result<()> {
+    let contents = fs::read_to_string(config_path)
+        .with_context(|| format!("Failed to read {}", config_path))?;
+    let mut config: toml::Table = toml::from_str(&contents)
+        .map_err(|e| anyhow!("{} is invalid: {}", config_path, e.message()))?;
+    config.insert(
+        "grpc_url".to_string(),
+        toml::Value::String(grpc_url.to_string()),
+    );
+
+    tracing::debug!("setting grpc_url in {} to {}", config_path, grpc_url);
+    write_atomic(config_path, &toml::to_string(&config)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrite_grpc_url_keeps_other_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = Utf8Path::from_path(dir.path()).unwrap().join("config.toml");
+        fs::write(
+            &config_path,
+            "full_viewing_key = \"fvk\"\ngrpc_url = \"http://localhost:8080/\"\n\n[custody]\nbackend = \"SoftKms\"\n",
+        )
+        .unwrap();
+
+        let grpc_url = Url::parse("https://grpc.testnet.penumbra.zone").unwrap();
+        rewrite_grpc_url(&config_path, &grpc_url).unwrap();
+
+        let config: toml::Table =
+            toml::from_str(&fs::read_to_string(&config_path).unwrap()).unwrap();
+        assert_eq!(
+            config["grpc_url"].as_str(),
+            Some("https://grpc.testnet.penumbra.zone/")
+        );
+        assert_eq!(config["full_viewing_key"].as_str(), Some("fvk"));
+        assert_eq!(config["custody"]["backend"].as_str(), Some("SoftKms"));
+    }
+}
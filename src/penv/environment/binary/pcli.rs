@@ -5,7 +5,7 @@ use camino::Utf8PathBuf;
 use regex::Regex;
 use url::Url;
 
-use super::{Binary, ManagedFile};
+use super::{rewrite_grpc_url, Binary, ManagedFile};
 
 pub(crate) struct PcliBinary {
     pub(crate) grpc_url: Url,
@@ -41,6 +41,15 @@ impl PcliBinary {
     pub fn pcli_data_dir(&self) -> Utf8PathBuf {
         self.root_dir.join("pcli")
     }
+
+    pub fn config_path(&self) -> Utf8PathBuf {
+        self.pcli_data_dir().join("config.toml")
+    }
+
+    /// Points the existing pcli config at the binary's GRPC URL.
+    pub fn set_grpc_url(&self) -> Result<()> {
+        rewrite_grpc_url(&self.config_path(), &self.grpc_url)
+    }
 }
 
 impl ManagedFile for PcliBinary {
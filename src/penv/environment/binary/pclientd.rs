@@ -8,7 +8,7 @@ use anyhow::{anyhow, Context as _, Result};
 use camino::Utf8PathBuf;
 use url::Url;
 
-use super::{Binary, ManagedFile};
+use super::{rewrite_grpc_url, Binary, ManagedFile};
 
 pub(crate) struct PclientdBinary {
     pub(crate) grpc_url: Url,
@@ -19,6 +19,15 @@ impl PclientdBinary {
     fn pclientd_data_dir(&self) -> Utf8PathBuf {
         self.root_dir.join("pclientd")
     }
+
+    pub fn config_path(&self) -> Utf8PathBuf {
+        self.pclientd_data_dir().join("config.toml")
+    }
+
+    /// Points the existing pclientd config at the binary's GRPC URL.
+    pub fn set_grpc_url(&self) -> Result<()> {
+        rewrite_grpc_url(&self.config_path(), &self.grpc_url)
+    }
 }
 
 impl ManagedFile for PclientdBinary {
@@ -1,4 +1,6 @@
 use std::{collections::HashMap, process::Command};
+#[cfg(unix)]
+use std::{thread, time::Duration};
 
 use anyhow::{anyhow, Context as _, Result};
 use camino::Utf8PathBuf;
@@ -12,9 +14,166 @@ pub(crate) struct PdBinary {
 }
 
 impl PdBinary {
-    fn network_data_dir(&self) -> Utf8PathBuf {
+    pub fn network_data_dir(&self) -> Utf8PathBuf {
         self.root_dir.join("network_data")
     }
+
+    // TODO: support multiple nodes
+    pub fn pd_home(&self) -> Utf8PathBuf {
+        self.network_data_dir().join("node0").join("pd")
+    }
+
+    pub fn cometbft_home(&self) -> Utf8PathBuf {
+        self.network_data_dir().join("node0").join("cometbft")
+    }
+
+    /// Exports the node state to the given directory, using the currently pinned pd.
+    ///
+    /// This is the step of a migration that must run with the old software version.
+    pub fn export(&self, export_dir: &Utf8PathBuf) -> Result<()> {
+        let pd_args = vec![
+            "export".to_string(),
+            "--home".to_string(),
+            self.pd_home().to_string(),
+            "--export-directory".to_string(),
+            export_dir.to_string(),
+        ];
+        self.run(pd_args).map(|_| ())
+    }
+
+    /// Migrates the node state in place, using the currently pinned pd.
+    ///
+    /// This is the step of a migration that must run with the new software version.
+    pub fn migrate(&self) -> Result<()> {
+        let pd_args = vec![
+            "migrate".to_string(),
+            "--home".to_string(),
+            self.pd_home().to_string(),
+            "--comet-home".to_string(),
+            self.cometbft_home().to_string(),
+        ];
+        self.run(pd_args).map(|_| ())
+    }
+
+    /// Stops any pd or cometbft processes running against this environment's node.
+    ///
+    /// Processes are identified by their command line: either the executable is one of
+    /// the environment's binaries, or it is a pd/cometbft executable pointed at the node's
+    /// directories. `extra_binaries` can be used to match additional paths a node may have
+    /// been started from, such as the global `$PENV_HOME/bin/pd` symlink.
+    ///
+    /// Returns whether any processes were stopped.
+    #[cfg(unix)]
+    pub fn stop_node(&self, extra_binaries: &[Utf8PathBuf]) -> Result<bool> {
+        let mut patterns = vec![format!("^{}( |$)", escape_pattern(self.path().as_str()))];
+        for binary in extra_binaries {
+            patterns.push(format!("^{}( |$)", escape_pattern(binary.as_str())));
+        }
+        for dir in [self.pd_home(), self.cometbft_home()] {
+            patterns.push(format!(
+                "^[^ ]*(pd|cometbft) .*{}",
+                escape_pattern(dir.as_str())
+            ));
+        }
+
+        let mut pids = Vec::new();
+        for pattern in patterns {
+            pids.extend(find_processes(&pattern)?);
+        }
+        pids.sort();
+        pids.dedup();
+        pids.retain(|pid| *pid != std::process::id());
+
+        if pids.is_empty() {
+            return Ok(false);
+        }
+
+        println!("stopping node processes {:?}", pids);
+        let status = Command::new("kill")
+            .arg("-TERM")
+            .args(pids.iter().map(|pid| pid.to_string()))
+            .status()?;
+        if !status.success() {
+            return Err(anyhow!("failed to stop node processes {:?}", pids));
+        }
+
+        // Give the node a chance to shut down cleanly before touching its data.
+        for _ in 0..30 {
+            if !pids.iter().any(|pid| process_exists(*pid)) {
+                return Ok(true);
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+
+        Err(anyhow!("node processes {:?} did not stop in time", pids))
+    }
+
+    /// Running processes can't be found on this platform, so the node can't be stopped
+    /// safely.
+    #[cfg(not(unix))]
+    pub fn stop_node(&self, _extra_binaries: &[Utf8PathBuf]) -> Result<bool> {
+        Err(anyhow!(
+            "stopping a running node is only supported on Unix, so penv can't make sure the node in {} is stopped",
+            self.network_data_dir()
+        ))
+    }
+
+    fn run(&self, pd_args: Vec<String>) -> Result<String> {
+        tracing::debug!(path=?self.path(), args=?pd_args, "executing pd binary");
+        let output = Command::new(self.path()).args(pd_args).output()?;
+
+        if output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            tracing::debug!(?stdout, "command output");
+            Ok(stdout.to_string())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(anyhow!("pd failed with error:\n{}", stderr))
+        }
+    }
+}
+
+/// Escapes a string for use in the extended regular expressions understood by `pgrep`.
+#[cfg(unix)]
+fn escape_pattern(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\.[]()*+?{}|^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Returns the pids of all processes whose command line matches the given pattern.
+#[cfg(unix)]
+fn find_processes(pattern: &str) -> Result<Vec<u32>> {
+    let output = Command::new("pgrep")
+        .arg("-f")
+        .arg(pattern)
+        .output()
+        .context("failed to run pgrep")?;
+
+    // pgrep exits with status 1 when no processes matched
+    if !output.status.success() && output.status.code() != Some(1) {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("pgrep failed with error:\n{}", stderr));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .collect())
+}
+
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    Command::new("kill")
+        .arg("-0")
+        .arg(pid.to_string())
+        .output()
+        .is_ok_and(|output| output.status.success())
 }
 
 impl ManagedFile for PdBinary {
@@ -1,4 +1,5 @@
-use anyhow::{Context as _, Result};
+use anyhow::{anyhow, Context as _, Result};
+use chrono::{DateTime, Utc};
 use serde::de::{self, MapAccess, Visitor};
 use serde::ser::SerializeStruct as _;
 use std::collections::HashMap;
@@ -11,28 +12,189 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::penv::{
     cache::cache::Cache,
+    config::Config,
     environment::{
-        create_symlink, Binary as _, EnvironmentMetadata, EnvironmentTrait, ManagedFile,
+        create_symlink, replace_symlinks, Binary as _, EnvironmentMetadata, EnvironmentTrait,
+        ManagedFile,
     },
-    release::{RepoOrVersion, VersionReqOrLatest},
+    release::{ReleaseChannel, RepoOrVersion, VersionReqOrLatest},
 };
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct BinaryEnvironment {
     /// Fields common to all environment types.
     pub metadata: EnvironmentMetadata,
+    /// The GitHub repository the environment's releases are installed from.
+    pub repository_name: String,
     /// The version_requirement is only set for binary releases.
     ///
     /// For git checkouts, there is no version -- the state of the checkout
     /// defines the code that will run.
     pub version_requirement: VersionReqOrLatest,
-    // TODO: implement a way to update the pinned_version
-    // to the latest matching the version_requirement
     /// The pinned_version is only set for binary releases.
     ///
     /// For git checkouts, there is no version -- the state of the checkout
     /// defines the code that will run.
     pub pinned_version: Version,
+    /// The releases a `latest` version requirement may resolve to.
+    pub channel: ReleaseChannel,
+    /// The versions the environment has been pinned to, oldest first.
+    ///
+    /// The last entry is the current pinned version; rolling back removes entries.
+    pub pin_history: Vec<PinnedVersion>,
+}
+
+/// An entry in an environment's pinned version history.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PinnedVersion {
+    pub version: Version,
+    /// When the version was pinned; unknown for environments created before
+    /// the history was recorded.
+    pub pinned_at: Option<DateTime<Utc>>,
+}
+
+impl Display for PinnedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pinned_at {
+            Some(pinned_at) => write!(
+                f,
+                "{} (pinned {})",
+                self.version,
+                pinned_at.format("%Y-%m-%d %H:%M:%S UTC")
+            ),
+            None => write!(f, "{}", self.version),
+        }
+    }
+}
+
+impl BinaryEnvironment {
+    /// Pins the environment to a new version, recording it in the history.
+    pub fn pin(&mut self, version: Version) {
+        self.seed_pin_history();
+        self.pin_history.push(PinnedVersion {
+            version: version.clone(),
+            pinned_at: Some(Utc::now()),
+        });
+        self.pinned_version = version;
+    }
+
+    /// Re-pins the environment to a version from its history, discarding the
+    /// entries pinned after it.
+    ///
+    /// Without a version, the environment is rolled back to the version pinned
+    /// before the current one.
+    pub fn rollback(&mut self, to: Option<&Version>) -> Result<()> {
+        self.seed_pin_history();
+
+        // The last entry is the current pin, so only earlier entries are candidates.
+        let previous = &self.pin_history[..self.pin_history.len() - 1];
+        let position = match to {
+            Some(version) if version == &self.pinned_version => {
+                return Err(anyhow!(
+                    "Environment {} is already pinned to version {}",
+                    self.metadata.alias,
+                    version
+                ))
+            }
+            Some(version) => previous
+                .iter()
+                .rposition(|p| &p.version == version)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Version {} is not in environment {}'s pinned version history",
+                        version,
+                        self.metadata.alias
+                    )
+                })?,
+            None => previous
+                .iter()
+                .rposition(|p| p.version != self.pinned_version)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Environment {} has no previously pinned version to roll back to",
+                        self.metadata.alias
+                    )
+                })?,
+        };
+
+        self.pin_history.truncate(position + 1);
+        self.pinned_version = self.pin_history[position].version.clone();
+
+        Ok(())
+    }
+
+    /// Environments created before the pinned version history was recorded start
+    /// their history with the current pinned version.
+    fn seed_pin_history(&mut self) {
+        if self.pin_history.is_empty() {
+            self.pin_history.push(PinnedVersion {
+                version: self.pinned_version.clone(),
+                pinned_at: None,
+            });
+        }
+    }
+
+    /// Returns the `(target, link)` pairs for the environment's binaries at the pinned version.
+    pub(crate) fn symlink_targets(&self, cache: &Cache) -> Result<Vec<(Utf8PathBuf, Utf8PathBuf)>> {
+        let pinned_version = &self.pinned_version;
+
+        let mut targets = vec![
+            (
+                cache
+                    .get_pcli_for_version(&self.repository_name, pinned_version)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "No installed pcli version found for version {}",
+                            pinned_version
+                        )
+                    })?
+                    .clone(),
+                self.pcli_path(),
+            ),
+            (
+                cache
+                    .get_pclientd_for_version(&self.repository_name, pinned_version)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "No installed pclientd version found for version {}",
+                            pinned_version
+                        )
+                    })?
+                    .clone(),
+                self.pclientd_path(),
+            ),
+        ];
+        if !self.metadata().client_only {
+            targets.push(self.pd_symlink_target(cache)?);
+        }
+
+        Ok(targets)
+    }
+
+    /// Returns the `(target, link)` pair for pd at the pinned version.
+    fn pd_symlink_target(&self, cache: &Cache) -> Result<(Utf8PathBuf, Utf8PathBuf)> {
+        let pinned_version = &self.pinned_version;
+
+        Ok((
+            cache
+                .get_pd_for_version(&self.repository_name, pinned_version)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No installed pd version found for version {}",
+                        pinned_version
+                    )
+                })?
+                .clone(),
+            self.pd_path(),
+        ))
+    }
+
+    /// Points the environment's symlinks at the pinned version, replacing existing links.
+    ///
+    /// Either all symlinks are switched, or none are.
+    pub fn switch_symlinks(&self, cache: &Cache) -> Result<()> {
+        replace_symlinks(&self.symlink_targets(cache)?)
+    }
 }
 
 impl Serialize for BinaryEnvironment {
@@ -40,9 +202,12 @@ impl Serialize for BinaryEnvironment {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("BinaryEnvironment", 3)?;
+        let mut state = serializer.serialize_struct("BinaryEnvironment", 6)?;
+        state.serialize_field("repository_name", &self.repository_name)?;
         state.serialize_field("pinned_version", &self.pinned_version)?;
         state.serialize_field("version_requirement", &self.version_requirement)?;
+        state.serialize_field("channel", &self.channel)?;
+        state.serialize_field("pin_history", &self.pin_history)?;
         state.serialize_field("metadata", &self.metadata)?;
         state.end()
     }
@@ -55,8 +220,11 @@ impl<'de> Deserialize<'de> for BinaryEnvironment {
     {
         enum Field {
             Metadata,
+            RepositoryName,
             PinnedVersion,
             VersionRequirement,
+            Channel,
+            PinHistory,
         }
 
         impl<'de> Deserialize<'de> for Field {
@@ -70,8 +238,9 @@ impl<'de> Deserialize<'de> for BinaryEnvironment {
                     type Value = Field;
 
                     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                        formatter
-                            .write_str("`metadata`, `pinned_version`, or `version_requirement`")
+                        formatter.write_str(
+                            "`metadata`, `repository_name`, `pinned_version`, `version_requirement`, `channel`, or `pin_history`",
+                        )
                     }
 
                     fn visit_str<E>(self, value: &str) -> Result<Field, E>
@@ -80,8 +249,11 @@ impl<'de> Deserialize<'de> for BinaryEnvironment {
                     {
                         match value {
                             "metadata" => Ok(Field::Metadata),
+                            "repository_name" => Ok(Field::RepositoryName),
                             "pinned_version" => Ok(Field::PinnedVersion),
                             "version_requirement" => Ok(Field::VersionRequirement),
+                            "channel" => Ok(Field::Channel),
+                            "pin_history" => Ok(Field::PinHistory),
                             _ => Err(de::Error::unknown_field(value, FIELDS)),
                         }
                     }
@@ -105,8 +277,11 @@ impl<'de> Deserialize<'de> for BinaryEnvironment {
                 V: MapAccess<'de>,
             {
                 let mut metadata: Option<EnvironmentMetadata> = None;
+                let mut repository_name: Option<String> = None;
                 let mut pinned_version: Option<Version> = None;
                 let mut version_requirement: Option<VersionReqOrLatest> = None;
+                let mut channel: Option<ReleaseChannel> = None;
+                let mut pin_history: Option<Vec<PinnedVersion>> = None;
 
                 while let Some(key) = map.next_key()? {
                     match key {
@@ -116,6 +291,12 @@ impl<'de> Deserialize<'de> for BinaryEnvironment {
                             }
                             metadata = Some(map.next_value()?);
                         }
+                        Field::RepositoryName => {
+                            if repository_name.is_some() {
+                                return Err(de::Error::duplicate_field("repository_name"));
+                            }
+                            repository_name = Some(map.next_value()?);
+                        }
                         Field::PinnedVersion => {
                             if pinned_version.is_some() {
                                 return Err(de::Error::duplicate_field("pinned_version"));
@@ -128,24 +309,51 @@ impl<'de> Deserialize<'de> for BinaryEnvironment {
                             }
                             version_requirement = Some(map.next_value()?);
                         }
+                        Field::Channel => {
+                            if channel.is_some() {
+                                return Err(de::Error::duplicate_field("channel"));
+                            }
+                            channel = Some(map.next_value()?);
+                        }
+                        Field::PinHistory => {
+                            if pin_history.is_some() {
+                                return Err(de::Error::duplicate_field("pin_history"));
+                            }
+                            pin_history = Some(map.next_value()?);
+                        }
                     }
                 }
 
                 let metadata = metadata.ok_or_else(|| de::Error::missing_field("metadata"))?;
+                let repository_name =
+                    repository_name.ok_or_else(|| de::Error::missing_field("repository_name"))?;
                 let pinned_version =
                     pinned_version.ok_or_else(|| de::Error::missing_field("pinned_version"))?;
                 let version_requirement = version_requirement
                     .ok_or_else(|| de::Error::missing_field("version_requirement"))?;
+                // Environments created before release channels existed only tracked stable releases.
+                let channel = channel.unwrap_or_default();
+                let pin_history = pin_history.unwrap_or_default();
 
                 Ok(BinaryEnvironment {
                     metadata,
+                    repository_name,
                     version_requirement,
                     pinned_version,
+                    channel,
+                    pin_history,
                 })
             }
         }
 
-        const FIELDS: &[&str] = &["metadata", "version_requirement", "pinned_version"];
+        const FIELDS: &[&str] = &[
+            "metadata",
+            "repository_name",
+            "version_requirement",
+            "pinned_version",
+            "channel",
+            "pin_history",
+        ];
         deserializer.deserialize_struct("BinaryEnvironment", FIELDS, BinaryEnvironmentVisitor)
     }
 }
@@ -160,8 +368,16 @@ impl Display for BinaryEnvironment {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "Alias: {}", self.metadata.alias)?;
         writeln!(f, "GRPC URL: {}", self.metadata.grpc_url)?;
+        writeln!(f, "Repository: {}", self.repository_name)?;
         writeln!(f, "Version Requirement: {}", self.version_requirement)?;
         writeln!(f, "Pinned Version: {}", self.pinned_version)?;
+        writeln!(f, "Release Channel: {}", self.channel)?;
+        if self.pin_history.len() > 1 {
+            writeln!(f, "Pinned Version History:")?;
+            for pinned in self.pin_history.iter().rev() {
+                writeln!(f, "  {}", pinned)?;
+            }
+        }
         writeln!(f, "Root Directory: {}", self.metadata.root_dir)?;
         writeln!(f, "Include Node: {}", !self.metadata.client_only)?;
         writeln!(
@@ -177,12 +393,13 @@ impl EnvironmentTrait for BinaryEnvironment {
     /// pd/pclientd/pcli configurations and symlinks to the
     /// pinned version of the software stack.
     fn initialize(&self, cache: &Cache) -> Result<()> {
-        self.initialize_with_seed_phrase(cache, None)
+        self.initialize_with_seed_phrase(cache, &Config::default(), None)
     }
 
     fn initialize_with_seed_phrase(
         &self,
         cache: &Cache,
+        config: &Config,
         import_seed_phrase: Option<String>,
     ) -> Result<()> {
         // Create the directory structure for the environment
@@ -215,72 +432,46 @@ impl EnvironmentTrait for BinaryEnvironment {
             seed_phrase,
         )])))?;
         if !self.metadata().client_only {
-            let pd_binary = self.get_pd_binary();
-            let mut pd_configs = HashMap::from([
-                (
-                    "external-address".to_string(),
-                    // TODO: make configurable
-                    "0.0.0.0:26656".to_string(),
-                ),
-                ("moniker".to_string(), self.metadata().alias.to_string()),
-            ]);
-
-            if self.metadata().generate_network {
-                pd_configs.insert("generate_network".to_string(), "true".to_string());
-            }
-
-            pd_binary.initialize(Some(pd_configs))?;
+            self.initialize_node(cache, config)?;
         }
 
         Ok(())
     }
 
-    fn create_symlinks(&self, cache: &Cache) -> Result<()> {
-        let pinned_version = &self.pinned_version;
+    fn initialize_node(&self, cache: &Cache, config: &Config) -> Result<()> {
+        let (target, link) = self.pd_symlink_target(cache)?;
+        create_symlink(&target, &link)
+            .with_context(|| format!("error creating {} symlink", link))?;
 
-        create_symlink(
-            cache.get_pcli_for_version(pinned_version).ok_or_else(|| {
-                anyhow::anyhow!(
-                    "No installed pcli version found for version {}",
-                    pinned_version
-                )
-            })?,
-            &self.pcli_path(),
-        )
-        .context("error creating pcli symlink")?;
-        create_symlink(
-            cache
-                .get_pclientd_for_version(pinned_version)
-                .ok_or_else(|| {
-                    anyhow::anyhow!(
-                        "No installed pclientd version found for version {}",
-                        pinned_version
-                    )
-                })?,
-            &self.pclientd_path(),
-        )
-        .context("error creating pclientd symlink")?;
-        if !self.metadata().client_only {
-            create_symlink(
-                cache.get_pd_for_version(pinned_version).ok_or_else(|| {
-                    anyhow::anyhow!(
-                        "No installed pd version found for version {}",
-                        pinned_version
-                    )
-                })?,
-                &self.pd_path(),
-            )
-            .context("error creating pd symlink")?;
+        let pd_binary = self.get_pd_binary();
+        if pd_binary.network_data_dir().exists() {
+            tracing::debug!("node already set up at {}", pd_binary.network_data_dir());
+            return Ok(());
         }
 
+        let mut pd_configs = HashMap::from([
+            ("external-address".to_string(), config.external_address()),
+            ("moniker".to_string(), self.metadata().alias.to_string()),
+        ]);
+
+        if self.metadata().generate_network {
+            pd_configs.insert("generate_network".to_string(), "true".to_string());
+
+            // Generated dev networks include a default allocation for convenience.
+            // Fetch the 0-index address of the environment's pcli
+            let address = self.get_pcli_binary().get_address(0)?;
+            pd_configs.insert("allocation_address".to_string(), address);
+        }
+
+        pd_binary.initialize(Some(pd_configs))?;
+
         Ok(())
     }
 
-    fn remove_symlinks(&self) -> Result<()> {
-        fs::remove_file(self.pcli_path())?;
-        fs::remove_file(self.pclientd_path())?;
-        if !self.metadata().client_only {
-            fs::remove_file(self.pd_path())?;
+    fn create_symlinks(&self, cache: &Cache) -> Result<()> {
+        for (target, link) in self.symlink_targets(cache)? {
+            create_symlink(&target, &link)
+                .with_context(|| format!("error creating {} symlink", link))?;
         }
 
         Ok(())
@@ -291,8 +482,10 @@ impl EnvironmentTrait for BinaryEnvironment {
             (VersionReqOrLatest::VersionReq(version_req), RepoOrVersion::Version(version)) => {
                 version_req.matches(version)
             }
-            (VersionReqOrLatest::Latest, RepoOrVersion::Version(_version)) => {
-                unimplemented!("don't have latest version here")
+            // Which version is latest depends on what's installed and available, so
+            // only the currently pinned version is known to satisfy the environment.
+            (VersionReqOrLatest::Latest, RepoOrVersion::Version(version)) => {
+                version == &self.pinned_version
             }
             // Latest never satisfied by a checkout
             (VersionReqOrLatest::Latest, RepoOrVersion::Repo(_repo)) => false,
@@ -342,8 +535,11 @@ mod tests {
 
         let env = BinaryEnvironment {
             metadata: metadata.clone(),
+            repository_name: "penumbra-zone/penumbra".to_string(),
             version_requirement: VersionReqOrLatest::Latest,
             pinned_version: Version::new(1, 0, 0),
+            channel: ReleaseChannel::All,
+            pin_history: vec![],
         };
 
         // Serialize to TOML string
@@ -351,7 +547,9 @@ mod tests {
 
         // Example TOML string for deserialization
         let toml_str = r#"
+            repository_name = "penumbra-zone/penumbra"
             pinned_version = "1.0.0"
+            channel = "all"
 
             [version_requirement]
             type = "Latest"
@@ -366,6 +564,63 @@ mod tests {
         "#;
 
         // Deserialize from TOML string
-        toml::from_str::<BinaryEnvironment>(toml_str).unwrap();
+        assert_eq!(toml::from_str::<BinaryEnvironment>(toml_str).unwrap(), env);
+
+        // Environments persisted without a channel default to stable
+        let toml_str = toml_str.replace("channel = \"all\"", "");
+        let env = toml::from_str::<BinaryEnvironment>(&toml_str).unwrap();
+        assert_eq!(env.channel, ReleaseChannel::Stable);
+    }
+
+    #[test]
+    fn pin_and_rollback() {
+        let mut env = BinaryEnvironment {
+            metadata: EnvironmentMetadata {
+                alias: "test".to_string(),
+                grpc_url: "http://localhost:9090".try_into().expect("ok"),
+                root_dir: "/tmp/fake".into(),
+                client_only: false,
+                generate_network: true,
+                pd_join_url: "http://localhost:9090".try_into().expect("ok"),
+            },
+            repository_name: "penumbra-zone/penumbra".to_string(),
+            version_requirement: VersionReqOrLatest::Latest,
+            pinned_version: Version::new(1, 0, 0),
+            channel: ReleaseChannel::Stable,
+            pin_history: vec![],
+        };
+
+        // Nothing to roll back to yet
+        assert!(env.rollback(None).is_err());
+
+        env.pin(Version::new(1, 1, 0));
+        env.pin(Version::new(2, 0, 0));
+        let versions = |env: &BinaryEnvironment| {
+            env.pin_history
+                .iter()
+                .map(|p| p.version.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(versions(&env), ["1.0.0", "1.1.0", "2.0.0"]);
+        assert!(env.pin_history[0].pinned_at.is_none());
+        assert!(env.pin_history[2].pinned_at.is_some());
+
+        // The history survives a round trip through the persisted format
+        let persisted = toml::to_string(&env).unwrap();
+        assert_eq!(
+            toml::from_str::<BinaryEnvironment>(&persisted).unwrap(),
+            env
+        );
+
+        env.rollback(None).unwrap();
+        assert_eq!(env.pinned_version, Version::new(1, 1, 0));
+        assert_eq!(versions(&env), ["1.0.0", "1.1.0"]);
+
+        env.pin(Version::new(2, 0, 0));
+        assert!(env.rollback(Some(&Version::new(3, 0, 0))).is_err());
+        assert!(env.rollback(Some(&Version::new(2, 0, 0))).is_err());
+        env.rollback(Some(&Version::new(1, 0, 0))).unwrap();
+        assert_eq!(env.pinned_version, Version::new(1, 0, 0));
+        assert_eq!(versions(&env), ["1.0.0"]);
     }
 }
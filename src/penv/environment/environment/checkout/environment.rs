@@ -2,17 +2,19 @@ use anyhow::{Context as _, Result};
 use std::collections::HashMap;
 use std::fmt::{self, Display};
 use std::fs::{self, File};
-use std::io::{self, Write as _};
+use std::io::Write as _;
 #[cfg(target_family = "unix")]
 use std::os::unix::fs::PermissionsExt as _;
-use std::path::Path;
 use std::sync::Arc;
 
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use serde::{Deserialize, Serialize};
 
 use crate::penv::cache::cache::Cache;
-use crate::penv::environment::{Binary as _, EnvironmentMetadata, EnvironmentTrait, ManagedFile};
+use crate::penv::config::Config;
+use crate::penv::environment::{
+    copy_dir_all, Binary as _, EnvironmentMetadata, EnvironmentTrait, ManagedFile,
+};
 use crate::penv::release::git_repo::CheckoutMetadata;
 use crate::penv::release::RepoOrVersion;
 
@@ -31,6 +33,30 @@ impl ManagedFile for CheckoutEnvironment {
     }
 }
 
+impl CheckoutEnvironment {
+    /// The environment's own copy of the git checkout, which its binaries are built from.
+    pub fn checkout_dir(&self) -> Utf8PathBuf {
+        self.path().join("checkout")
+    }
+
+    /// Writes the wrapper scripts that build and run the environment's binaries from
+    /// its checkout, replacing existing ones.
+    pub fn write_wrappers(&self) -> Result<()> {
+        let checkout_dir = self.checkout_dir();
+        write_wrapper("pcli", &checkout_dir, &self.get_pcli_binary().path())?;
+        write_wrapper(
+            "pclientd",
+            &checkout_dir,
+            &self.get_pclientd_binary().path(),
+        )?;
+        if !self.metadata.client_only {
+            write_wrapper("pd", &checkout_dir, &self.get_pd_binary().path())?;
+        }
+
+        Ok(())
+    }
+}
+
 impl Display for CheckoutEnvironment {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "Alias: {}", self.metadata.alias)?;
@@ -47,33 +73,20 @@ impl Display for CheckoutEnvironment {
     }
 }
 
-fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()> {
-    fs::create_dir_all(&dst)?;
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let ty = entry.file_type()?;
-        if ty.is_dir() {
-            copy_dir_all(entry.path(), dst.as_ref().join(entry.file_name()))?;
-        } else {
-            fs::copy(entry.path(), dst.as_ref().join(entry.file_name()))?;
-        }
-    }
-    Ok(())
-}
-
 impl EnvironmentTrait for CheckoutEnvironment {
     fn initialize(&self, _cache: &Cache) -> Result<()> {
-        self.initialize_with_seed_phrase(_cache, None)
+        self.initialize_with_seed_phrase(_cache, &Config::default(), None)
     }
 
     fn initialize_with_seed_phrase(
         &self,
-        _cache: &Cache,
+        cache: &Cache,
+        config: &Config,
         import_seed_phrase: Option<String>,
     ) -> Result<()> {
         // Create the directory structure for the environment
         let bin_dir = self.path().join("bin");
-        let checkout_dir = self.path().join("checkout");
+        let checkout_dir = self.checkout_dir();
         tracing::debug!("creating environment dir at {}", bin_dir);
         fs::create_dir_all(&bin_dir)
             .with_context(|| format!("Failed to create environment bin directory {}", bin_dir))?;
@@ -86,67 +99,8 @@ impl EnvironmentTrait for CheckoutEnvironment {
         );
         copy_dir_all(&self.git_checkout.install_path, &checkout_dir)?;
 
-        // Handle writing the hook scripts into the bin dir
-        let mut context = tera::Context::new();
-        context.insert("checkout_dir", &checkout_dir.to_string());
-
-        // TODO: this should live in some kind of Hook struct or trait or something
-        // we don't have access to the current shell here unfortunately, so either
-        // these are cross-shell or we need to pass the shell in
-        // also the relative paths are kinda wild here, this should be abstracted
-        let pcliwrapper_template = include_str!("../../../../../files/zsh-pcliwrapper.j2");
-        let pcliwrapper = tera::Tera::one_off(pcliwrapper_template, &context, false)?;
-        let pcli_binary = self.get_pcli_binary();
-
-        let mut pcli_file = File::create(pcli_binary.path())?;
-
-        tracing::debug!(?pcliwrapper, "writing pcliwrapper");
-        // Write the rendered pcli wrapper to the pcli binary location
-        pcli_file.write_all(pcliwrapper.as_bytes())?;
-
-        #[cfg(target_family = "unix")]
-        {
-            let pcli_metadata = fs::metadata(pcli_binary.path())?;
-            let mut pcli_permissions = pcli_metadata.permissions();
-            pcli_permissions.set_mode(pcli_permissions.mode() | 0o111); // Add executable bit
-            fs::set_permissions(pcli_binary.path(), pcli_permissions)?;
-        }
-
-        let pclientdwrapper_template = include_str!("../../../../../files/zsh-pclientdwrapper.j2");
-        let pclientdwrapper = tera::Tera::one_off(pclientdwrapper_template, &context, false)?;
-        let pclientd_binary = self.get_pclientd_binary();
-
-        let mut pclientd_file = File::create(pclientd_binary.path())?;
-
-        pclientd_file.write_all(pclientdwrapper.as_bytes())?;
-
-        #[cfg(target_family = "unix")]
-        {
-            let pclientd_metadata = fs::metadata(pclientd_binary.path())?;
-            let mut pclientd_permissions = pclientd_metadata.permissions();
-            pclientd_permissions.set_mode(pclientd_permissions.mode() | 0o111); // Add executable bit
-            fs::set_permissions(pclientd_binary.path(), pclientd_permissions)?;
-        }
-
-        if !self.metadata().client_only {
-            context.insert("create_pd", "true");
-
-            let pdwrapper_template = include_str!("../../../../../files/zsh-pdwrapper.j2");
-            let pdwrapper = tera::Tera::one_off(pdwrapper_template, &context, false)?;
-            let pd_binary = self.get_pd_binary();
-
-            let mut pd_file = File::create(pd_binary.path())?;
-
-            pd_file.write_all(pdwrapper.as_bytes())?;
-
-            #[cfg(target_family = "unix")]
-            {
-                let pd_metadata = fs::metadata(pd_binary.path())?;
-                let mut pd_permissions = pd_metadata.permissions();
-                pd_permissions.set_mode(pd_permissions.mode() | 0o111); // Add executable bit
-                fs::set_permissions(pd_binary.path(), pd_permissions)?;
-            }
-        }
+        // Write wrapper scripts into the bin dir that build and run each binary from the checkout
+        self.write_wrappers()?;
 
         // If the environment is set to generate a local dev network,
         // we must initialize that prior to pcli and pclientd.
@@ -155,6 +109,7 @@ impl EnvironmentTrait for CheckoutEnvironment {
         if let Some(seed_phrase) = import_seed_phrase.clone() {
             pcli_configs.insert("import_seed_phrase".to_string(), seed_phrase);
         }
+        let pcli_binary = self.get_pcli_binary();
         let seed_phrase = pcli_binary.initialize(Some(pcli_configs))?;
         // TODO: lol don't do this
         tracing::debug!("seed phrase: {}", seed_phrase);
@@ -165,28 +120,37 @@ impl EnvironmentTrait for CheckoutEnvironment {
             seed_phrase,
         )])))?;
         if !self.metadata().client_only {
-            let pd_binary = self.get_pd_binary();
-            let mut pd_configs = HashMap::from([
-                (
-                    "external-address".to_string(),
-                    // TODO: make configurable
-                    "0.0.0.0:26656".to_string(),
-                ),
-                ("moniker".to_string(), self.metadata().alias.to_string()),
-            ]);
-
-            if self.metadata().generate_network {
-                pd_configs.insert("generate_network".to_string(), "true".to_string());
-
-                // Generated dev networks include a default allocation for convenience.
-                // Fetch the 0-index address of the environment's pcli
-                let address = pcli_binary.get_address(0)?;
-                pd_configs.insert("allocation_address".to_string(), address);
-            }
+            self.initialize_node(cache, config)?;
+        }
+
+        Ok(())
+    }
+
+    fn initialize_node(&self, _cache: &Cache, config: &Config) -> Result<()> {
+        let pd_binary = self.get_pd_binary();
+        write_wrapper("pd", &self.checkout_dir(), &pd_binary.path())?;
+
+        if pd_binary.network_data_dir().exists() {
+            tracing::debug!("node already set up at {}", pd_binary.network_data_dir());
+            return Ok(());
+        }
+
+        let mut pd_configs = HashMap::from([
+            ("external-address".to_string(), config.external_address()),
+            ("moniker".to_string(), self.metadata().alias.to_string()),
+        ]);
 
-            pd_binary.initialize(Some(pd_configs))?;
+        if self.metadata().generate_network {
+            pd_configs.insert("generate_network".to_string(), "true".to_string());
+
+            // Generated dev networks include a default allocation for convenience.
+            // Fetch the 0-index address of the environment's pcli
+            let address = self.get_pcli_binary().get_address(0)?;
+            pd_configs.insert("allocation_address".to_string(), address);
         }
 
+        pd_binary.initialize(Some(pd_configs))?;
+
         Ok(())
     }
 
@@ -208,9 +172,47 @@ impl EnvironmentTrait for CheckoutEnvironment {
         // Nothing to do here, the hook will handle setting the aliases
         Ok(())
     }
+}
 
-    fn remove_symlinks(&self) -> Result<()> {
-        // Nothing to do here, the hook will handle setting the aliases
-        Ok(())
+/// Writes an executable `/bin/sh` script to `path` that runs `binary` from the checkout.
+fn write_wrapper(binary: &str, checkout_dir: &Utf8Path, path: &Utf8Path) -> Result<()> {
+    let mut context = tera::Context::new();
+    context.insert("binary", binary);
+    context.insert("checkout_dir", &shell_quote(checkout_dir.as_str()));
+
+    let wrapper_template = include_str!("../../../../../files/checkout-wrapper.j2");
+    let wrapper = tera::Tera::one_off(wrapper_template, &context, false)?;
+
+    tracing::debug!(?wrapper, "writing {} wrapper", binary);
+    let mut file = File::create(path)
+        .with_context(|| format!("Failed to create {} wrapper {}", binary, path))?;
+    file.write_all(wrapper.as_bytes())?;
+
+    #[cfg(target_family = "unix")]
+    {
+        let mut permissions = fs::metadata(path)?.permissions();
+        permissions.set_mode(permissions.mode() | 0o111); // Add executable bit
+        fs::set_permissions(path, permissions)?;
+    }
+
+    Ok(())
+}
+
+/// Quotes a string for use as a single word in a POSIX shell script.
+pub(crate) fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::shell_quote;
+
+    #[test]
+    fn quote_for_shell() {
+        assert_eq!(shell_quote("/home/user/penumbra"), "'/home/user/penumbra'");
+        assert_eq!(
+            shell_quote("/home/user/it's \"$penumbra\""),
+            "'/home/user/it'\\''s \"$penumbra\"'"
+        );
     }
 }
@@ -4,18 +4,21 @@ use std::fmt::{self, Display};
 use std::os::unix::fs::{symlink as unix_symlink, PermissionsExt as _};
 #[cfg(target_family = "windows")]
 use std::os::windows::fs::symlink_file as windows_symlink_file;
+use std::path::Path;
 use std::sync::Arc;
 use std::{
-    fs,
+    fs, io,
     ops::{Deref, DerefMut},
 };
 
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use serde::{Deserialize, Serialize};
-use url::Url;
+use url::{Host, Url};
 
 use crate::penv::cache::cache::Cache;
+use crate::penv::config::Config;
 use crate::penv::release::RepoOrVersion;
+use crate::penv::state::write_atomic;
 
 use super::{ManagedFile, PcliBinary, PclientdBinary, PdBinary};
 
@@ -42,6 +45,15 @@ impl ManagedFile for Environment {
     }
 }
 
+impl Environment {
+    pub fn metadata_mut(&mut self) -> &mut EnvironmentMetadata {
+        match self {
+            Environment::CheckoutEnvironment(env) => &mut env.metadata,
+            Environment::BinaryEnvironment(env) => &mut env.metadata,
+        }
+    }
+}
+
 impl Display for Environment {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -62,31 +74,31 @@ impl EnvironmentTrait for Environment {
     fn initialize_with_seed_phrase(
         &self,
         cache: &Cache,
+        config: &Config,
         import_seed_phrase: Option<String>,
     ) -> Result<()> {
         match self {
             Environment::CheckoutEnvironment(env) => {
-                env.initialize_with_seed_phrase(cache, import_seed_phrase)
+                env.initialize_with_seed_phrase(cache, config, import_seed_phrase)
             }
             Environment::BinaryEnvironment(env) => {
-                env.initialize_with_seed_phrase(cache, import_seed_phrase)
+                env.initialize_with_seed_phrase(cache, config, import_seed_phrase)
             }
         }
     }
 
-    fn create_symlinks(&self, cache: &Cache) -> Result<()> {
+    fn initialize_node(&self, cache: &Cache, config: &Config) -> Result<()> {
         match self {
-            // no symlinks are created for git checkout environments
-            Environment::CheckoutEnvironment(_env) => Ok(()),
-            Environment::BinaryEnvironment(env) => env.create_symlinks(cache),
+            Environment::CheckoutEnvironment(env) => env.initialize_node(cache, config),
+            Environment::BinaryEnvironment(env) => env.initialize_node(cache, config),
         }
     }
 
-    fn remove_symlinks(&self) -> Result<()> {
+    fn create_symlinks(&self, cache: &Cache) -> Result<()> {
         match self {
             // no symlinks are created for git checkout environments
             Environment::CheckoutEnvironment(_env) => Ok(()),
-            Environment::BinaryEnvironment(env) => env.remove_symlinks(),
+            Environment::BinaryEnvironment(env) => env.create_symlinks(cache),
         }
     }
 
@@ -112,27 +124,102 @@ pub struct EnvironmentMetadata {
     pub grpc_url: Url,
     pub root_dir: Utf8PathBuf,
     pub pd_join_url: Url,
-    // whether there should be a pd config generated as well
-    // TODO: would be useful to be able to change this for an existing config
-    // but requires special pd initialization step
+    /// Whether the environment only runs clients, without a pd node.
     pub client_only: bool,
     /// Whether a dev network should be generated or an existing network should be joined.
     pub generate_network: bool,
 }
 
+impl EnvironmentMetadata {
+    /// The kind of network the environment targets, guessed from its URLs since that's
+    /// all that is known about it without network access.
+    ///
+    /// Environments whose URLs all point at this machine or a private network are
+    /// assumed to be devnets. Anything else that isn't recognizably a devnet or testnet
+    /// is assumed to be mainnet, so that it's never mistaken for a network where
+    /// mistakes are cheap.
+    pub fn network(&self) -> Network {
+        if self.generate_network {
+            return Network::Devnet;
+        }
+
+        let urls = [&self.grpc_url, &self.pd_join_url];
+        let hosts = urls
+            .iter()
+            .filter_map(|url| url.host_str())
+            .collect::<Vec<_>>();
+        if hosts.iter().any(|host| host.contains("devnet")) {
+            Network::Devnet
+        } else if hosts.iter().any(|host| host.contains("testnet")) {
+            Network::Testnet
+        } else if urls.iter().all(|url| is_local(url)) {
+            Network::Devnet
+        } else {
+            Network::Mainnet
+        }
+    }
+}
+
+/// Whether the URL points at this machine or a private network.
+fn is_local(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(domain)) => {
+            domain == "localhost" || domain.ends_with(".localhost") || domain.ends_with(".local")
+        }
+        Some(Host::Ipv4(ip)) => {
+            ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+        }
+        Some(Host::Ipv6(ip)) => {
+            ip.is_loopback()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+                || ip.is_unspecified()
+        }
+        None => false,
+    }
+}
+
+/// The kinds of networks an environment can target.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Network {
+    Devnet,
+    Testnet,
+    Mainnet,
+}
+
+impl Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Network::Devnet => write!(f, "devnet"),
+            Network::Testnet => write!(f, "testnet"),
+            Network::Mainnet => write!(f, "mainnet"),
+        }
+    }
+}
+
 pub trait EnvironmentTrait: ManagedFile {
     /// Initializes an environment on disk, by creating the necessary
     /// pd/pclientd/pcli configurations and symlinks to the
     /// pinned version of the software stack.
     fn initialize(&self, cache: &Cache) -> Result<()>;
 
-    /// Initializes an environment with an optional seed phrase for import.
+    /// Initializes an environment with an optional seed phrase for import, using the
+    /// user's configuration for settings such as pd's external address.
     fn initialize_with_seed_phrase(
         &self,
         cache: &Cache,
+        config: &Config,
         import_seed_phrase: Option<String>,
     ) -> Result<()>;
 
+    /// Makes pd available in the environment and sets up its node, by joining the
+    /// network or generating a dev network.
+    ///
+    /// A node that was already set up, for example before the environment was made
+    /// client-only, is kept as it is.
+    fn initialize_node(&self, cache: &Cache, config: &Config) -> Result<()>;
+
     fn get_pcli_binary(&self) -> PcliBinary {
         PcliBinary {
             root_dir: self.path().clone(),
@@ -166,15 +253,86 @@ pub trait EnvironmentTrait: ManagedFile {
         self.path().join("bin/pd")
     }
 
-    fn create_symlinks(&self, cache: &Cache) -> Result<()>;
+    fn pcli_home(&self) -> Utf8PathBuf {
+        self.path().join("pcli")
+    }
+
+    fn pclientd_home(&self) -> Utf8PathBuf {
+        self.path().join("pclientd")
+    }
+
+    fn pd_home(&self) -> Utf8PathBuf {
+        self.path().join("network_data").join("node0").join("pd")
+    }
+
+    fn cometbft_home(&self) -> Utf8PathBuf {
+        self.path()
+            .join("network_data")
+            .join("node0")
+            .join("cometbft")
+    }
+
+    fn bin_dir(&self) -> Utf8PathBuf {
+        self.path().join("bin")
+    }
+
+    /// The environment variables that point the Penumbra software stack at this environment.
+    ///
+    /// Variables from [`ENVIRONMENT_VARIABLE_NAMES`] that are missing don't apply to
+    /// this environment, and should be unset.
+    fn environment_variables(&self) -> Vec<EnvironmentVariable> {
+        let metadata = self.metadata();
+        let mut variables = vec![
+            EnvironmentVariable::new("PENV_ACTIVE_ENVIRONMENT", &metadata.alias),
+            EnvironmentVariable::new("PENUMBRA_PCLI_HOME", self.pcli_home()),
+            EnvironmentVariable::new("PENUMBRA_PCLIENTD_HOME", self.pclientd_home()),
+        ];
+        if !metadata.client_only {
+            variables.extend([
+                EnvironmentVariable::new("PENUMBRA_PD_HOME", self.pd_home()),
+                EnvironmentVariable::new("PENUMBRA_PD_COMETBFT_PROXY_URL", &metadata.pd_join_url),
+                EnvironmentVariable::new("PENUMBRA_PD_JOIN_URL", &metadata.pd_join_url),
+                EnvironmentVariable::new("COMETBFT_HOME", self.cometbft_home()),
+            ]);
+        }
 
-    fn remove_symlinks(&self) -> Result<()>;
+        variables
+    }
+
+    fn create_symlinks(&self, cache: &Cache) -> Result<()>;
 
     fn satisfied_by_version(&self, version: &RepoOrVersion) -> bool;
 
     fn metadata(&self) -> &EnvironmentMetadata;
 }
 
+/// The names of all environment variables set for an environment.
+pub const ENVIRONMENT_VARIABLE_NAMES: [&str; 7] = [
+    "PENV_ACTIVE_ENVIRONMENT",
+    "PENUMBRA_PCLI_HOME",
+    "PENUMBRA_PCLIENTD_HOME",
+    "PENUMBRA_PD_HOME",
+    "PENUMBRA_PD_COMETBFT_PROXY_URL",
+    "PENUMBRA_PD_JOIN_URL",
+    "COMETBFT_HOME",
+];
+
+/// An environment variable set for an environment, see [`EnvironmentTrait::environment_variables`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct EnvironmentVariable {
+    pub name: &'static str,
+    pub value: String,
+}
+
+impl EnvironmentVariable {
+    fn new(name: &'static str, value: impl Display) -> Self {
+        Self {
+            name,
+            value: value.to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Environments {
     pub environments: Vec<Arc<Environment>>,
@@ -225,6 +383,133 @@ pub fn create_symlink(target: &Utf8PathBuf, link: &Utf8PathBuf) -> Result<()> {
     Ok(())
 }
 
+/// Points each `(target, link)` symlink at its new target, replacing existing links.
+///
+/// The switch is transactional: if any link can't be replaced, the links that were
+/// already switched are restored to their previous targets before returning the error.
+pub fn replace_symlinks(links: &[(Utf8PathBuf, Utf8PathBuf)]) -> Result<()> {
+    let mut switched: Vec<(&Utf8PathBuf, Option<Utf8PathBuf>)> = Vec::new();
+
+    for (target, link) in links {
+        let previous_target = fs::read_link(link)
+            .ok()
+            .and_then(|path| Utf8PathBuf::from_path_buf(path).ok());
+
+        if let Err(e) = replace_symlink(target, link) {
+            for (link, previous_target) in switched.into_iter().rev() {
+                let restored = match previous_target {
+                    Some(previous_target) => replace_symlink(&previous_target, link),
+                    None => fs::remove_file(link).map_err(Into::into),
+                };
+                if let Err(restore_error) = restored {
+                    tracing::warn!("failed to restore symlink {}: {}", link, restore_error);
+                }
+            }
+
+            return Err(e.context(format!("failed to point {} at {}", link, target)));
+        }
+
+        switched.push((link, previous_target));
+    }
+
+    Ok(())
+}
+
+/// Atomically replaces a symlink by creating the new link next to it and renaming it into place.
+fn replace_symlink(target: &Utf8PathBuf, link: &Utf8PathBuf) -> Result<()> {
+    let file_name = link
+        .file_name()
+        .ok_or_else(|| anyhow!("symlink {} has no file name", link))?;
+    let temp_link = link.with_file_name(format!(".{}.penv-tmp", file_name));
+    if fs::symlink_metadata(&temp_link).is_ok() {
+        fs::remove_file(&temp_link)?;
+    }
+
+    create_symlink(target, &temp_link)?;
+    if let Err(e) = fs::rename(&temp_link, link) {
+        let _ = fs::remove_file(&temp_link);
+        return Err(e.into());
+    }
+
+    Ok(())
+}
+
+/// Recursively copies the contents of the `src` directory into `dst`.
+pub fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()> {
+    fs::create_dir_all(&dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let ty = entry.file_type()?;
+        if ty.is_dir() {
+            copy_dir_all(entry.path(), dst.as_ref().join(entry.file_name()))?;
+        } else {
+            fs::copy(entry.path(), dst.as_ref().join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces `from` with `to` in the generated configs below `dir`, such as those of
+/// pcli, pclientd and CometBFT, which embed absolute paths.
+///
+/// Only TOML and JSON files are rewritten, leaving the node's data untouched, and
+/// symlinks are not followed.
+pub fn rewrite_config_paths(dir: &Utf8Path, from: &Utf8Path, to: &Utf8Path) -> Result<()> {
+    for entry in dir.read_dir_utf8()? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            rewrite_config_paths(path, from, to)?;
+            continue;
+        }
+        if !file_type.is_file() || !matches!(path.extension(), Some("toml" | "json")) {
+            continue;
+        }
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            // Not a text file, so it can't be a config.
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(rewritten) = replace_path(&contents, from, to) {
+            tracing::debug!("rewriting paths in {}", path);
+            write_atomic(path, &rewritten)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the path `from` with `to` in `contents`, including where it's the start of
+/// a longer path, but not where it's only part of a path, such as in `/x/.penv-old` for
+/// `/x/.penv`. Returns `None` if nothing was replaced.
+fn replace_path(contents: &str, from: &Utf8Path, to: &Utf8Path) -> Option<String> {
+    let is_path_char = |c: char| c.is_alphanumeric() || "-_./~".contains(c);
+
+    let mut rewritten = String::with_capacity(contents.len());
+    let mut rest = contents;
+    let mut replaced = false;
+    while let Some(start) = rest.find(from.as_str()) {
+        let end = start + from.as_str().len();
+        let before = rest[..start].chars().next_back();
+        let after = rest[end..].chars().next();
+        rewritten.push_str(&rest[..start]);
+        if !before.is_some_and(is_path_char) && !after.is_some_and(|c| c != '/' && is_path_char(c))
+        {
+            rewritten.push_str(to.as_str());
+            replaced = true;
+        } else {
+            rewritten.push_str(from.as_str());
+        }
+        rest = &rest[end..];
+    }
+    rewritten.push_str(rest);
+
+    replaced.then_some(rewritten)
+}
+
 impl Environments {
     pub fn get_environment(&self, environment_alias: &str) -> Option<Arc<Environment>> {
         self.environments
@@ -233,3 +518,88 @@ impl Environments {
             .cloned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_from_urls() {
+        let network = |grpc_url: &str, pd_join_url: &str| {
+            EnvironmentMetadata {
+                alias: "test".into(),
+                grpc_url: Url::parse(grpc_url).unwrap(),
+                root_dir: "/tmp/test".into(),
+                pd_join_url: Url::parse(pd_join_url).unwrap(),
+                client_only: false,
+                generate_network: false,
+            }
+            .network()
+        };
+
+        assert_eq!(
+            network(
+                "https://grpc.devnet.penumbra.zone",
+                "https://rpc.devnet.penumbra.zone"
+            ),
+            Network::Devnet
+        );
+        assert_eq!(
+            network(
+                "https://grpc.testnet.penumbra.zone",
+                "https://rpc.testnet.penumbra.zone"
+            ),
+            Network::Testnet
+        );
+        assert_eq!(
+            network("https://penumbra.example.com", "https://rpc.example.com"),
+            Network::Mainnet
+        );
+
+        // Nodes on this machine or a private network.
+        for url in [
+            "http://localhost:8080",
+            "http://127.0.0.1:8080",
+            "http://[::1]:8080",
+            "http://10.0.0.5:8080",
+            "http://192.168.1.20:8080",
+            "http://172.16.0.1:8080",
+            "http://[fd00::1]:8080",
+            "http://node.local:8080",
+        ] {
+            assert_eq!(
+                network(url, "http://127.0.0.1:26657"),
+                Network::Devnet,
+                "{}",
+                url
+            );
+        }
+
+        // A local node joined to a public network is on that network.
+        assert_eq!(
+            network("http://localhost:8080", "https://rpc.example.com"),
+            Network::Mainnet
+        );
+        assert_eq!(
+            network("http://localhost:8080", "https://rpc.testnet.penumbra.zone"),
+            Network::Testnet
+        );
+        // Public addresses aren't local, even if they look similar.
+        assert_eq!(
+            network("http://172.32.0.1:8080", "http://127.0.0.1:26657"),
+            Network::Mainnet
+        );
+
+        let mut generated = EnvironmentMetadata {
+            alias: "test".into(),
+            grpc_url: Url::parse("https://penumbra.example.com").unwrap(),
+            root_dir: "/tmp/test".into(),
+            pd_join_url: Url::parse("https://rpc.example.com").unwrap(),
+            client_only: false,
+            generate_network: true,
+        };
+        assert_eq!(generated.network(), Network::Devnet);
+        generated.generate_network = false;
+        assert_eq!(generated.network(), Network::Mainnet);
+    }
+}
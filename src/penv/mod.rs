@@ -1,7 +1,11 @@
 pub mod cache;
+pub mod config;
 pub mod downloader;
 pub mod environment;
 pub mod penv;
+pub mod project;
 pub mod release;
+pub mod snapshot;
+pub mod state;
 
 pub use penv::Penv;
@@ -1,14 +1,14 @@
 use std::{
-    fmt,
-    fs::{self, File},
-    io::Write as _,
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
     process::Command,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
 use anyhow::{anyhow, Context as _, Result};
-use camino::Utf8PathBuf;
-use semver::Version;
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::Utc;
+use semver::{Version, VersionReq};
 use serde::{
     de::{self, MapAccess, Visitor},
     ser::SerializeStruct as _,
@@ -21,19 +21,23 @@ use url::Url;
 
 use crate::penv::{
     cache::cache::CacheData,
+    config::Config,
     environment::{
-        BinaryEnvironment, CheckoutEnvironment, Environment, EnvironmentMetadata, EnvironmentTrait,
-        ManagedFile,
+        copy_dir_all, BinaryEnvironment, CheckoutEnvironment, Environment, EnvironmentMetadata,
+        EnvironmentTrait, ManagedFile, PdBinary, PinnedVersion,
     },
+    project::{shell_environment, EnvironmentSource, ProjectFile},
     release::{
-        git_repo::RepoMetadata, InstallableRelease, InstalledRelease, Release, RepoOrVersion,
+        git_repo::RepoMetadata, InstallableRelease, InstalledRelease, Release, ReleaseChannel,
+        RepoOrVersion, TagPattern, VersionReqOrLatest,
     },
+    state::{write_atomic, StateFile},
 };
 
 use super::{
     cache::cache::Cache,
     downloader::Downloader,
-    environment::{create_symlink, Environments},
+    environment::{create_symlink, replace_symlinks, rewrite_config_paths, Environments},
     release::RepoOrVersionReq,
 };
 
@@ -43,10 +47,19 @@ use super::{
 /// operations.
 pub struct Penv {
     pub cache: Cache,
-    pub(crate) downloader: Downloader,
+    /// The user's configuration, from `config.toml` and the environment.
+    pub config: Config,
+    /// The downloaders for each GitHub repository used so far, keyed by repository name.
+    downloaders: Mutex<HashMap<String, Downloader>>,
     pub environments: Environments,
-    pub repository_name: String,
+    /// Maps each repository's release tags to versions, keyed by repository name.
+    ///
+    /// Repositories without an entry use the default pattern, `v{version}`.
+    pub tag_patterns: BTreeMap<String, TagPattern>,
     pub home_dir: Utf8PathBuf,
+    /// The home the environments' generated configs and symlinks were written for, if
+    /// the home was moved since without `penv relocate`.
+    pub previous_home: Option<Utf8PathBuf>,
     pub active_environment: Option<Arc<Environment>>,
 }
 
@@ -55,9 +68,14 @@ impl Serialize for Penv {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Penv", 5)?;
-        state.serialize_field("repository_name", &self.repository_name)?;
-        state.serialize_field("home_dir", &self.home_dir)?;
+        let mut state = serializer.serialize_struct("Penv", 4)?;
+        state.serialize_field("tag_patterns", &self.tag_patterns)?;
+        // Recording the previous home until relocating keeps track of which paths the
+        // generated configs and symlinks have to be rewritten from.
+        state.serialize_field(
+            "home_dir",
+            self.previous_home.as_ref().unwrap_or(&self.home_dir),
+        )?;
         state.serialize_field(
             "active_environment",
             &self
@@ -65,7 +83,6 @@ impl Serialize for Penv {
                 .clone()
                 .map(|e| e.metadata().alias.clone()),
         )?;
-        state.serialize_field("cache", &self.cache.data)?;
         state.serialize_field("environments", &self.environments)?;
         state.end()
     }
@@ -78,10 +95,9 @@ impl<'de> Deserialize<'de> for Penv {
     {
         enum Field {
             Environments,
-            RepositoryName,
+            TagPatterns,
             HomeDir,
             ActiveEnvironment,
-            Cache,
         }
 
         impl<'de> Deserialize<'de> for Field {
@@ -95,7 +111,9 @@ impl<'de> Deserialize<'de> for Penv {
                     type Value = Field;
 
                     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                        formatter.write_str("`repository_name`, `home_dir`, `active_environment`, `cache`, or `environments`")
+                        formatter.write_str(
+                            "`tag_patterns`, `home_dir`, `active_environment`, or `environments`",
+                        )
                     }
 
                     fn visit_str<E>(self, value: &str) -> Result<Field, E>
@@ -103,11 +121,10 @@ impl<'de> Deserialize<'de> for Penv {
                         E: de::Error,
                     {
                         match value {
-                            "repository_name" => Ok(Field::RepositoryName),
+                            "tag_patterns" => Ok(Field::TagPatterns),
                             "home_dir" => Ok(Field::HomeDir),
                             "environments" => Ok(Field::Environments),
                             "active_environment" => Ok(Field::ActiveEnvironment),
-                            "cache" => Ok(Field::Cache),
                             _ => Err(de::Error::unknown_field(value, FIELDS)),
                         }
                     }
@@ -130,19 +147,18 @@ impl<'de> Deserialize<'de> for Penv {
             where
                 V: MapAccess<'de>,
             {
-                let mut repository_name: Option<String> = None;
+                let mut tag_patterns: Option<BTreeMap<String, TagPattern>> = None;
                 let mut home_dir: Option<Utf8PathBuf> = None;
                 let mut environments: Option<Environments> = None;
                 let mut active_environment_alias: Option<String> = None;
-                let mut cache: Option<CacheData> = None;
 
                 while let Some(key) = map.next_key()? {
                     match key {
-                        Field::RepositoryName => {
-                            if repository_name.is_some() {
-                                return Err(de::Error::duplicate_field("repository_name"));
+                        Field::TagPatterns => {
+                            if tag_patterns.is_some() {
+                                return Err(de::Error::duplicate_field("tag_patterns"));
                             }
-                            repository_name = Some(map.next_value()?);
+                            tag_patterns = Some(map.next_value()?);
                         }
                         Field::HomeDir => {
                             if home_dir.is_some() {
@@ -162,17 +178,10 @@ impl<'de> Deserialize<'de> for Penv {
                             }
                             active_environment_alias = Some(map.next_value()?);
                         }
-                        Field::Cache => {
-                            if cache.is_some() {
-                                return Err(de::Error::duplicate_field("cache"));
-                            }
-                            cache = Some(map.next_value()?);
-                        }
                     }
                 }
 
-                let repository_name =
-                    repository_name.ok_or_else(|| de::Error::missing_field("repository_name"))?;
+                let tag_patterns = tag_patterns.unwrap_or_default();
                 let home_dir = home_dir.ok_or_else(|| de::Error::missing_field("home_dir"))?;
                 let environments =
                     environments.ok_or_else(|| de::Error::missing_field("environments"))?;
@@ -182,24 +191,32 @@ impl<'de> Deserialize<'de> for Penv {
                         .find(|e| e.metadata().alias == alias)
                         .cloned()
                 });
+                // The installed releases are kept in `cache.toml`, and the configuration
+                // in `config.toml`, which `Penv::new` loads.
                 let cache = Cache {
                     home: home_dir.clone(),
-                    data: cache.ok_or_else(|| de::Error::missing_field("cache"))?,
+                    data: CacheData::default(),
+                };
+                let config = Config {
+                    home: home_dir.clone(),
+                    ..Config::default()
                 };
 
                 Ok(Penv {
-                    repository_name: repository_name.clone(),
+                    tag_patterns,
                     home_dir: home_dir.clone(),
+                    previous_home: None,
                     environments,
                     cache,
-                    downloader: Downloader::new(repository_name).map_err(de::Error::custom)?,
+                    config,
+                    downloaders: Mutex::new(HashMap::new()),
                     active_environment,
                 })
             }
         }
 
         const FIELDS: &[&str] = &[
-            "repository_name",
+            "tag_patterns",
             "home_dir",
             "environments",
             "active_environment",
@@ -211,55 +228,92 @@ impl<'de> Deserialize<'de> for Penv {
 impl Penv {
     /// Create a new instance of the Penumbra Environment Manager.
     pub fn new(home: Utf8PathBuf) -> Result<Self> {
-        // read config file to fetch existing environments
-        let penv_path = home.join("penv.toml");
-        let metadata = fs::metadata(&penv_path);
+        // The cache is loaded first, as migrating it reads the installed releases that
+        // older versions of `penv.toml` kept a copy of.
+        let cache = Cache::new(home.clone())?;
+        let config = Config::load(&home)?;
 
-        let penv = if metadata.is_err() || !metadata.unwrap().is_file() {
-            Self {
-                cache: Cache::new(home.clone())?,
-                downloader: Downloader::new("penumbra-zone/penumbra".to_string())?,
+        // read config file to fetch existing environments
+        let penv = match StateFile::Penv.load::<Self>(&home)? {
+            // The recorded home differs from the actual one if the home was moved.
+            Some(penv) => Self {
+                cache,
+                config,
+                previous_home: (penv.home_dir != home).then(|| penv.home_dir.clone()),
+                home_dir: home,
+                ..penv
+            },
+            None => Self {
+                cache,
+                config,
+                downloaders: Mutex::new(HashMap::new()),
                 environments: Environments {
                     environments: Vec::new(),
                 },
-                // TODO: shouldn't be hardcoded here
-                repository_name: "penumbra-zone/penumbra".to_string(),
+                tag_patterns: BTreeMap::new(),
                 home_dir: home,
+                previous_home: None,
                 active_environment: None,
-            }
-        } else {
-            let penv_contents = fs::read_to_string(penv_path)?;
-            toml::from_str(&penv_contents)?
+            },
         };
 
         tracing::debug!(environments=?penv.environments, installed_releases=?penv.cache.data.installed_releases, "created penv with environments");
         Ok(penv)
     }
 
-    // TODO: delete this method and handle alternative repositories better
-    pub fn new_from_repository(repository_name: String, home: Utf8PathBuf) -> Result<Self> {
-        // read config file to fetch existing environments
-        let penv_path = home.join("penv.toml");
-        let metadata = fs::metadata(&penv_path);
+    /// Returns the downloader for the GitHub repository `repository_name`.
+    ///
+    /// Downloaders are shared for the lifetime of the instance, so that each repository's
+    /// releases are only fetched once.
+    pub(crate) fn downloader(&self, repository_name: &str) -> Result<Downloader> {
+        let mut downloaders = self
+            .downloaders
+            .lock()
+            .map_err(|_| anyhow!("downloaders lock poisoned"))?;
+        if let Some(downloader) = downloaders.get(repository_name) {
+            return Ok(downloader.clone());
+        }
 
-        let penv = if metadata.is_err() || !metadata.unwrap().is_file() {
-            Self {
-                cache: Cache::new(home.clone())?,
-                downloader: Downloader::new(repository_name.clone())?,
-                environments: Environments {
-                    environments: Vec::new(),
-                },
-                repository_name,
-                home_dir: home.clone(),
-                active_environment: None,
-            }
+        let downloader = Downloader::new(
+            repository_name.to_string(),
+            self.tag_pattern(repository_name),
+            self.config.github_token(),
+            self.home_dir.clone(),
+        )?;
+        downloaders.insert(repository_name.to_string(), downloader.clone());
+
+        Ok(downloader)
+    }
+
+    /// Returns the pattern mapping the release tags of `repository_name` to versions.
+    pub fn tag_pattern(&self, repository_name: &str) -> TagPattern {
+        self.tag_patterns
+            .get(repository_name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Sets the pattern used to map the release tags of `repository_name` to versions.
+    ///
+    /// The pattern is persisted immediately, so later commands use it as well.
+    pub fn set_tag_pattern(
+        &mut self,
+        repository_name: &str,
+        tag_pattern: TagPattern,
+    ) -> Result<()> {
+        if tag_pattern == TagPattern::default() {
+            self.tag_patterns.remove(repository_name);
         } else {
-            let penv_contents = fs::read_to_string(penv_path)?;
-            toml::from_str(&penv_contents)?
-        };
+            self.tag_patterns
+                .insert(repository_name.to_string(), tag_pattern);
+        }
+        // Releases fetched with the previous pattern may have been parsed differently.
+        self.downloaders
+            .lock()
+            .map_err(|_| anyhow!("downloaders lock poisoned"))?
+            .remove(repository_name);
 
-        tracing::debug!(environments=?penv.environments, installed_releases=?penv.cache.data.installed_releases, "created penv with environments");
-        Ok(penv)
+        self.persist()
     }
 
     /// Deactivate the current environment, removing any symlinks.
@@ -267,7 +321,8 @@ impl Penv {
         self.active_environment = None;
         // Unset the symlink
         let link = self.home_dir.join("bin");
-        let link_metadata = fs::metadata(link.clone());
+        // The link itself is inspected, so that a dangling link is removed as well.
+        let link_metadata = fs::symlink_metadata(link.clone());
         tracing::debug!("link_metadata: {:?}", link_metadata);
         if let Ok(link_metadata) = link_metadata {
             if link_metadata.is_symlink() || link_metadata.is_file() {
@@ -428,10 +483,11 @@ impl Penv {
         grpc_url: Url,
         pd_join_url: Url,
         // eventually allow auto-download
-        _repository_name: String,
+        repository_name: String,
         client_only: bool,
         generate_network: bool,
         import_seed_phrase: Option<String>,
+        channel: ReleaseChannel,
     ) -> Result<Arc<Environment>> {
         if self
             .environments
@@ -446,16 +502,18 @@ impl Penv {
 
         // Find the best matching version
         let cache = &self.cache;
-        let matching_installed_version = match cache.find_best_match(&penumbra_version) {
-            Some(installed_version) => installed_version,
-            None => {
-                // TODO: allow auto-installing here
-                return Err(anyhow!(
-                    "No installed version found for version requirement {}",
-                    penumbra_version
-                ));
-            }
-        };
+        let matching_installed_version =
+            match cache.find_best_match(&repository_name, &penumbra_version, channel) {
+                Some(installed_version) => installed_version,
+                None => {
+                    // TODO: allow auto-installing here
+                    return Err(anyhow!(
+                        "No installed version from {} found for version requirement {}",
+                        repository_name,
+                        penumbra_version
+                    ));
+                }
+            };
 
         match *matching_installed_version {
             InstalledRelease::GitCheckout(ref release) => {
@@ -480,7 +538,11 @@ impl Penv {
 
                 tracing::debug!("initializing environment");
                 // Copy the checkout into the environment dir.
-                environment.initialize_with_seed_phrase(cache, import_seed_phrase.clone())?;
+                environment.initialize_with_seed_phrase(
+                    cache,
+                    &self.config,
+                    import_seed_phrase.clone(),
+                )?;
 
                 tracing::debug!("created environment: {:?}", environment);
 
@@ -516,12 +578,22 @@ impl Penv {
                         pd_join_url,
                         generate_network,
                     },
+                    repository_name,
                     version_requirement,
+                    pin_history: vec![PinnedVersion {
+                        version: pinned_version.clone(),
+                        pinned_at: Some(Utc::now()),
+                    }],
                     pinned_version,
+                    channel,
                 }));
 
                 tracing::debug!("initializing environment");
-                environment.initialize_with_seed_phrase(cache, import_seed_phrase.clone())?;
+                environment.initialize_with_seed_phrase(
+                    cache,
+                    &self.config,
+                    import_seed_phrase.clone(),
+                )?;
 
                 tracing::debug!("created environment: {:?}", environment);
 
@@ -535,31 +607,41 @@ impl Penv {
         }
     }
 
-    /// Returns all available versions and whether they're installed, optionally matching a given semver version requirement.
+    /// Returns all versions available from the GitHub repository `repository_name` and
+    /// whether they're installed, optionally matching a given semver version requirement.
     pub async fn list_available(
         &self,
+        repository_name: &str,
         required_version: Option<&RepoOrVersionReq>,
+        channel: ReleaseChannel,
     ) -> Result<Vec<(Release, bool)>> {
         self.cache
-            .list_available(required_version, &self.downloader)
+            .list_available(
+                required_version,
+                channel,
+                &self.downloader(repository_name)?,
+            )
             .await
     }
 
+    /// Installs a git repo, or the newest release from the GitHub repository
+    /// `repository_name` matching a version requirement.
     pub async fn install_release(
         &mut self,
+        repository_name: &str,
         penumbra_version: RepoOrVersionReq,
+        channel: ReleaseChannel,
         target_arch: Triple,
     ) -> Result<()> {
         let installable_release = {
-            let mut candidate_releases = Vec::new();
-
             match penumbra_version {
                 // a Repo requirement will never meet a version returned from the binary release downloader
                 // TODO: split downloader into a binary release downloader and git repo downloader
                 RepoOrVersionReq::Repo(ref repo_url) => {
-                    let installed_release = self
-                        .cache
-                        .get_installed_release(&RepoOrVersion::Repo(repo_url.clone()));
+                    let installed_release = self.cache.get_installed_release(
+                        repository_name,
+                        &RepoOrVersion::Repo(repo_url.clone()),
+                    );
 
                     // TODO: actually use gix and try to validate the checkout
                     // let target_repo_dir_metadata = fs::metadata(target_repo_dir.clone());
@@ -585,73 +667,27 @@ impl Penv {
                     }))
                 }
                 RepoOrVersionReq::VersionReqOrLatest(ref penumbra_version) => {
-                    let downloader = &self.downloader;
+                    let downloader = self.downloader(repository_name)?;
                     let releases = downloader.fetch_releases().await?;
-                    let latest_version = releases
-                        .iter()
-                        .max()
-                        .ok_or_else(|| anyhow!("No releases found"))?
-                        .version
-                        .clone();
-
-                    // 3b. find all the versions that satisfy the semver requirement
-                    'outer: for release in releases {
-                        if penumbra_version.matches(&release.version, &latest_version) {
-                            let release_name = release.name.clone();
-                            tracing::debug!("found candidate release {}", release_name);
-                            let enriched_release: Release = match release.try_into() {
-                                Ok(enriched_release) => enriched_release,
-                                Err(e) => {
-                                    tracing::debug!(
-                                    "failed to enrich release {}, not making an install candidate: {}",
-                                    release_name,
-                                    e
-                                );
-                                    continue;
-                                }
-                            };
-
-                            // Typically a release should contain all assets for all architectures,
-                            // but if it doesn't, this may produce unexpected failures.
-                            //
-                            // If the candidate release has no assets for the target architecture, skip it
-                            let has_arch_asset = enriched_release.assets.iter().any(|asset| {
-                                asset.target_arch.is_some()
-                                    && asset.target_arch.clone().unwrap() == Triple::host()
-                            });
-                            if !has_arch_asset {
-                                tracing::debug!(
-                                "skipping release {} because it has no assets for the target architecture",
-                                enriched_release.name
-                            );
-                                continue 'outer;
-                            }
-
-                            candidate_releases.push(enriched_release);
-                        }
-                    }
-
-                    if candidate_releases.is_empty() {
-                        return Err(anyhow!("No matching release found for version requirement"));
-                    }
-
-                    // 4. identify the latest candidate version
-                    let mut sorted_releases = candidate_releases.clone();
-                    sorted_releases.sort();
-
-                    let latest_release = sorted_releases.last().unwrap();
-
-                    let cache = &mut self.cache;
+                    let latest_release =
+                        latest_matching_release(&releases, penumbra_version, channel, &target_arch)
+                            .ok_or_else(|| {
+                                anyhow!("No matching release found for version requirement")
+                            })?;
 
                     // Skip installation if the installed_releases already contains this release
-                    if cache.data.installed_releases.iter().any(|r| {
-                        match *r {
-                            InstalledRelease::Binary(ref r) => r.version == latest_release.version,
-                            // TODO: implement for git checkouts
-                            InstalledRelease::GitCheckout(_) => false,
-                        }
-                    }) {
-                        println!("release {} already installed", latest_release.version);
+                    if self
+                        .cache
+                        .get_installed_release(
+                            repository_name,
+                            &RepoOrVersion::Version(latest_release.version.clone()),
+                        )
+                        .is_some()
+                    {
+                        println!(
+                            "release {} from {} already installed",
+                            latest_release.version, repository_name
+                        );
                         return Ok(());
                     }
 
@@ -680,6 +716,400 @@ impl Penv {
         Ok(())
     }
 
+    /// Downloads and installs a specific release from the GitHub repository
+    /// `repository_name` to the cache.
+    async fn install_binary_release(
+        &mut self,
+        repository_name: &str,
+        release: &Release,
+        target_arch: &Triple,
+    ) -> Result<()> {
+        println!("downloading release: {}", release.version);
+        let installable_release = self
+            .downloader(repository_name)?
+            .download_release(release, format!("{}", target_arch))
+            .await?;
+
+        println!("installing release: {}", installable_release);
+        self.cache.install_release(&installable_release)?;
+
+        self.persist()
+    }
+
+    /// Upgrades a binary environment's pinned version to the newest version matching its
+    /// version requirement.
+    ///
+    /// If `fetch` is set, the upstream releases are consulted and a newer matching release
+    /// is installed before re-pinning. Otherwise only installed versions are considered.
+    ///
+    /// A `latest` requirement resolves against the given channel, falling back to the
+    /// environment's own channel.
+    ///
+    /// Returns the version the environment was upgraded to, or `None` if it's already
+    /// pinned to the latest matching version.
+    pub async fn upgrade_environment(
+        &mut self,
+        environment_alias: &str,
+        fetch: bool,
+        channel: Option<ReleaseChannel>,
+        target_arch: Triple,
+    ) -> Result<Option<Version>> {
+        let environment = self
+            .environments
+            .get_environment(environment_alias)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Environment with alias {} does not exist",
+                    environment_alias
+                )
+            })?;
+
+        let (repository_name, version_requirement, pinned_version, channel) = match *environment {
+            Environment::BinaryEnvironment(ref env) => (
+                env.repository_name.clone(),
+                env.version_requirement.clone(),
+                env.pinned_version.clone(),
+                channel.unwrap_or(env.channel),
+            ),
+            Environment::CheckoutEnvironment(_) => {
+                return Err(anyhow!(
+                    "checkout environments are not supported for upgrades"
+                ))
+            }
+        };
+
+        let target_version = if fetch {
+            let releases = self.downloader(&repository_name)?.fetch_releases().await?;
+            let target_release =
+                latest_matching_release(&releases, &version_requirement, channel, &target_arch)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "No release found for version requirement {}",
+                            version_requirement
+                        )
+                    })?;
+
+            if target_release.version <= pinned_version {
+                println!(
+                    "Environment {}'s pinned version {} is the latest available version matching version requirement {}",
+                    environment_alias, pinned_version, version_requirement
+                );
+                return Ok(None);
+            }
+
+            print_changelog(&releases, &pinned_version, &target_release.version);
+
+            if self
+                .cache
+                .get_installed_release(
+                    &repository_name,
+                    &RepoOrVersion::Version(target_release.version.clone()),
+                )
+                .is_none()
+            {
+                self.install_binary_release(&repository_name, target_release, &target_arch)
+                    .await?;
+            }
+
+            target_release.version.clone()
+        } else {
+            let penumbra_version = RepoOrVersionReq::VersionReqOrLatest(version_requirement);
+            let matching_installed_version =
+                match self
+                    .cache
+                    .find_best_match(&repository_name, &penumbra_version, channel)
+                {
+                    Some(InstalledRelease::Binary(installed_version)) => installed_version,
+                    Some(InstalledRelease::GitCheckout(_)) => {
+                        unreachable!("git checkout environments are not supported for upgrades")
+                    }
+                    None => {
+                        return Err(anyhow!(
+                            "No installed version found for version requirement {}",
+                            penumbra_version
+                        ));
+                    }
+                };
+
+            // A `latest` environment may be pinned ahead of the newest installed version
+            // on its channel, e.g. after a manual pin; never downgrade in that case.
+            if matching_installed_version.version <= pinned_version {
+                println!(
+                    "Environment {}'s pinned version {} is the latest installed version matching version requirement {}",
+                    environment_alias, pinned_version, penumbra_version
+                );
+                return Ok(None);
+            }
+
+            matching_installed_version.version.clone()
+        };
+
+        println!(
+            "Updating environment {}'s pinned version from {} to {}",
+            environment_alias, pinned_version, target_version
+        );
+        self.replace_version(environment_alias.to_string(), target_version.clone())?;
+
+        Ok(Some(target_version))
+    }
+
+    /// Migrates an environment to a new, state-breaking software version.
+    ///
+    /// The node is stopped and its network data backed up. The old pd then exports the node
+    /// state, the environment is re-pinned, and the new pd migrates the state in place.
+    /// Finally the client state is reset, since the view database no longer matches the chain.
+    ///
+    /// If any step fails, the network data is restored from the backup and the old version
+    /// is pinned again.
+    pub fn migrate_environment(
+        &mut self,
+        environment_alias: String,
+        new_version: Version,
+    ) -> Result<()> {
+        let environment = self
+            .environments
+            .get_environment(&environment_alias)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Environment with alias {} does not exist",
+                    environment_alias
+                )
+            })?;
+
+        let (repository_name, old_version, old_requirement) = match *environment {
+            Environment::BinaryEnvironment(ref env) => (
+                env.repository_name.clone(),
+                env.pinned_version.clone(),
+                env.version_requirement.clone(),
+            ),
+            Environment::CheckoutEnvironment(_) => {
+                return Err(anyhow!("Cannot migrate a checkout environment"));
+            }
+        };
+
+        if new_version <= old_version {
+            return Err(anyhow!(
+                "Version {} is not newer than the pinned version {} of environment {}",
+                new_version,
+                old_version,
+                environment_alias
+            ));
+        }
+
+        if self
+            .cache
+            .get_installed_release(
+                &repository_name,
+                &RepoOrVersion::Version(new_version.clone()),
+            )
+            .is_none()
+        {
+            return Err(anyhow!(
+                "Version {} from {} is not installed; run `penv install --repository-name {} {}` first",
+                new_version,
+                repository_name,
+                repository_name,
+                new_version
+            ));
+        }
+
+        let client_only = environment.metadata().client_only;
+        let pd_binary = environment.get_pd_binary();
+        let backups_dir = environment.path().join("backups");
+        let backup_dir = backups_dir.join(format!("network_data-{}", old_version));
+        let export_dir = backups_dir.join(format!("export-{}", old_version));
+
+        if !client_only {
+            if backup_dir.exists() || export_dir.exists() {
+                return Err(anyhow!(
+                    "A migration backup for version {} already exists in {}; remove it before migrating again",
+                    old_version,
+                    backups_dir
+                ));
+            }
+
+            if pd_binary.stop_node(&self.global_node_binaries(&environment_alias))? {
+                println!("stopped node for environment {}", environment_alias);
+            }
+
+            println!("backing up network data to {}", backup_dir);
+            fs::create_dir_all(&backups_dir)?;
+            copy_dir_all(pd_binary.network_data_dir(), &backup_dir)
+                .context("failed to back up network data")?;
+        }
+
+        let migration = self.apply_migration(
+            &environment_alias,
+            &pd_binary,
+            &export_dir,
+            &new_version,
+            client_only,
+        );
+
+        if let Err(e) = migration {
+            println!(
+                "migration failed, rolling back environment {} to {}",
+                environment_alias, old_version
+            );
+            self.rollback_migration(
+                &environment_alias,
+                &pd_binary,
+                &backup_dir,
+                &export_dir,
+                &old_version,
+                &old_requirement,
+                client_only,
+            )
+            .with_context(|| format!("failed to roll back after migration error: {e:#}"))?;
+            return Err(e);
+        }
+
+        println!(
+            "migrated environment {} from {} to {}",
+            environment_alias, old_version, new_version
+        );
+        if !client_only {
+            println!("the pre-migration network data is kept at {}", backup_dir);
+        }
+
+        Ok(())
+    }
+
+    fn apply_migration(
+        &mut self,
+        environment_alias: &str,
+        pd_binary: &PdBinary,
+        export_dir: &Utf8PathBuf,
+        new_version: &Version,
+        client_only: bool,
+    ) -> Result<()> {
+        if !client_only {
+            println!("exporting node state to {}", export_dir);
+            pd_binary
+                .export(export_dir)
+                .context("failed to export node state with the old pd version")?;
+        }
+
+        self.repin_environment(environment_alias, |e| {
+            // The environment would otherwise be pinned outside its own requirement, and
+            // its new version could be deleted from the cache as unused.
+            if let VersionReqOrLatest::VersionReq(version_req) = &e.version_requirement {
+                if !version_req.matches(new_version) {
+                    let new_requirement = VersionReq::parse(&format!("^{}", new_version))?;
+                    println!(
+                        "updating the version requirement of environment {} from {} to {}",
+                        environment_alias, version_req, new_requirement
+                    );
+                    e.version_requirement = VersionReqOrLatest::VersionReq(new_requirement);
+                }
+            }
+            e.pin(new_version.clone());
+            Ok(())
+        })?;
+
+        if !client_only {
+            println!("migrating node state with pd {}", new_version);
+            pd_binary
+                .migrate()
+                .context("failed to migrate node state with the new pd version")?;
+        }
+
+        // The client's view database was built against the pre-migration chain.
+        self.reset_environment(environment_alias.to_string(), false, true)
+    }
+
+    fn rollback_migration(
+        &mut self,
+        environment_alias: &str,
+        pd_binary: &PdBinary,
+        backup_dir: &Utf8PathBuf,
+        export_dir: &Utf8PathBuf,
+        old_version: &Version,
+        old_requirement: &VersionReqOrLatest,
+        client_only: bool,
+    ) -> Result<()> {
+        if !client_only {
+            let network_data_dir = pd_binary.network_data_dir();
+            if network_data_dir.exists() {
+                fs::remove_dir_all(&network_data_dir)?;
+            }
+            copy_dir_all(backup_dir, &network_data_dir)
+                .context("failed to restore network data from backup")?;
+
+            // The backup has been restored, so clear it to allow retrying the migration.
+            fs::remove_dir_all(backup_dir)?;
+            if export_dir.exists() {
+                fs::remove_dir_all(export_dir)?;
+            }
+        }
+
+        let pinned_version = match self.environments.get_environment(environment_alias) {
+            Some(environment) => match *environment {
+                Environment::BinaryEnvironment(ref env) => env.pinned_version.clone(),
+                Environment::CheckoutEnvironment(_) => unreachable!("checked before migrating"),
+            },
+            None => return Err(anyhow!("Environment {} disappeared", environment_alias)),
+        };
+        if &pinned_version != old_version {
+            self.repin_environment(environment_alias, |e| {
+                e.rollback(Some(old_version))?;
+                e.version_requirement = old_requirement.clone();
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Updates the environments after the home was moved from the previous home, by
+    /// rewriting their generated configs and recreating their symlinks and wrapper
+    /// scripts, and records the new home.
+    ///
+    /// Environments that can't be updated are reported, but don't stop the others from
+    /// being updated.
+    pub fn finish_relocation(&mut self) -> Result<()> {
+        for environment in self.environments.iter() {
+            let mut updated = Ok(());
+            if let Some(previous_home) = &self.previous_home {
+                if environment.path().is_dir() {
+                    updated =
+                        rewrite_config_paths(&environment.path(), previous_home, &self.home_dir);
+                }
+            }
+            // The symlinks and wrapper scripts refer to the binaries by their absolute paths.
+            updated = updated.and_then(|()| match environment.as_ref() {
+                Environment::BinaryEnvironment(env) => env.switch_symlinks(&self.cache),
+                Environment::CheckoutEnvironment(env) => env.write_wrappers(),
+            });
+
+            if let Err(e) = updated {
+                eprintln!(
+                    "failed to update environment {}: {:#}",
+                    environment.metadata().alias,
+                    e
+                );
+            }
+        }
+
+        if let Some(active_environment) = &self.active_environment {
+            replace_symlinks(&[(
+                active_environment.path().join("bin"),
+                self.home_dir.join("bin"),
+            )])
+            .context("error updating the active environment's symlink")?;
+        }
+
+        // The cached output of `penv env` refers to the previous home.
+        let snapshots = self.home_dir.join("snapshots");
+        if snapshots.is_dir() {
+            fs::remove_dir_all(&snapshots)
+                .with_context(|| format!("Failed to remove {}", snapshots))?;
+        }
+
+        self.previous_home = None;
+        self.persist()
+    }
+
     pub fn penv_file_path(&self) -> Utf8PathBuf {
         self.home_dir.join("penv.toml")
     }
@@ -688,12 +1118,11 @@ impl Penv {
         fs::create_dir_all(&self.home_dir)
             .with_context(|| format!("Failed to create home directory {}", self.home_dir))?;
 
-        let toml_penv = toml::to_string(&self)?;
+        let toml_penv = StateFile::Penv.serialize_contents(&self.home_dir, &self)?;
 
         tracing::debug!(penv_file_path=?self.penv_file_path(),"create file");
 
-        let mut file = File::create(self.penv_file_path())?;
-        file.write_all(toml_penv.as_bytes())?;
+        write_atomic(&self.penv_file_path(), &toml_penv)?;
 
         tracing::debug!("persist cache");
         self.cache.persist()?;
@@ -734,50 +1163,220 @@ impl Penv {
         Ok(environment)
     }
 
+    /// Pins a binary environment to a new version, recording it in the environment's
+    /// pinned version history.
     pub fn replace_version(
         &mut self,
         environment_alias: String,
         new_version: Version,
     ) -> Result<()> {
-        let mut environment = self
+        self.repin_environment(&environment_alias, |e| {
+            e.pin(new_version);
+            Ok(())
+        })
+    }
+
+    /// Re-pins a binary environment to a version from its pinned version history,
+    /// by default the version pinned before the current one.
+    ///
+    /// Returns the version the environment was rolled back to.
+    pub fn rollback_environment(
+        &mut self,
+        environment_alias: String,
+        to: Option<Version>,
+    ) -> Result<Version> {
+        self.repin_environment(&environment_alias, |e| e.rollback(to.as_ref()))?;
+
+        match *self
             .environments
-            .iter()
-            .find(|e| e.metadata().alias == environment_alias)
-            .ok_or_else(|| anyhow!("Environment with alias {} not found", environment_alias))?
-            .clone();
+            .get_environment(&environment_alias)
+            .ok_or_else(|| anyhow!("Environment {} disappeared", environment_alias))?
+        {
+            Environment::BinaryEnvironment(ref e) => Ok(e.pinned_version.clone()),
+            Environment::CheckoutEnvironment(_) => unreachable!("checked when re-pinning"),
+        }
+    }
 
-        let e = Arc::make_mut(&mut environment);
+    /// Applies a change of pinned version to a binary environment.
+    ///
+    /// The environment's symlinks are switched to the new version before the state is
+    /// persisted; if either step fails, the symlinks and state are left as they were.
+    fn repin_environment(
+        &mut self,
+        environment_alias: &str,
+        repin: impl FnOnce(&mut BinaryEnvironment) -> Result<()>,
+    ) -> Result<()> {
+        let environment = self
+            .environments
+            .get_environment(environment_alias)
+            .ok_or_else(|| anyhow!("Environment with alias {} not found", environment_alias))?;
 
-        match *e {
-            Environment::BinaryEnvironment(ref mut e) => {
-                e.pinned_version = new_version;
-            }
+        let previous = match *environment {
+            Environment::BinaryEnvironment(ref e) => e.clone(),
             Environment::CheckoutEnvironment(_) => {
                 return Err(anyhow!("Cannot replace version for a checkout environment"));
             }
+        };
+
+        let mut updated = previous.clone();
+        repin(&mut updated)?;
+
+        updated.switch_symlinks(&self.cache)?;
+
+        let previous = Arc::new(Environment::BinaryEnvironment(previous));
+        let updated = Arc::new(Environment::BinaryEnvironment(updated));
+        self.set_environment(environment_alias, updated);
+
+        if let Err(e) = self.persist() {
+            if let Environment::BinaryEnvironment(ref previous) = *previous {
+                if let Err(restore_error) = previous.switch_symlinks(&self.cache) {
+                    tracing::warn!("failed to restore symlinks: {}", restore_error);
+                }
+            }
+            self.set_environment(environment_alias, previous);
+
+            return Err(e);
         }
 
-        let e = Arc::new(e.clone());
+        Ok(())
+    }
 
-        // Remove the environment from the environment list
+    /// Changes the URLs or client-only mode of an existing environment, keeping its wallet.
+    ///
+    /// The pcli and pclientd configs are pointed at a new GRPC URL in place. Switching a
+    /// client-only environment to a full node sets up pd, while switching a full node to
+    /// client-only removes pd from the environment but keeps the node's data, so that
+    /// switching back reuses it.
+    pub fn edit_environment(
+        &mut self,
+        environment_alias: &str,
+        grpc_url: Option<Url>,
+        pd_join_url: Option<Url>,
+        client_only: Option<bool>,
+    ) -> Result<Arc<Environment>> {
+        let environment = self
+            .environments
+            .get_environment(environment_alias)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Environment with alias {} does not exist",
+                    environment_alias
+                )
+            })?;
+
+        let mut updated = environment.as_ref().clone();
+        let metadata = updated.metadata_mut();
+        if let Some(grpc_url) = grpc_url {
+            metadata.grpc_url = grpc_url;
+        }
+        if let Some(pd_join_url) = pd_join_url {
+            metadata.pd_join_url = pd_join_url;
+        }
+        if let Some(client_only) = client_only {
+            metadata.client_only = client_only;
+        }
+
+        let previous = environment.metadata();
+        let metadata = updated.metadata();
+        if metadata == previous {
+            return Err(anyhow!(
+                "Environment {} already has these settings",
+                environment_alias
+            ));
+        }
+
+        if !previous.client_only && metadata.client_only {
+            // A running node would keep using the pd binary that's about to be removed.
+            if updated
+                .get_pd_binary()
+                .stop_node(&self.global_node_binaries(environment_alias))?
+            {
+                println!("stopped node for environment {}", environment_alias);
+            }
+        }
+
+        // Keep the previous configs, so that they can be restored if a later step fails.
+        let mut previous_configs = Vec::new();
+        if metadata.grpc_url != previous.grpc_url {
+            for config_path in [
+                updated.get_pcli_binary().config_path(),
+                updated.get_pclientd_binary().config_path(),
+            ] {
+                let contents = fs::read_to_string(&config_path)
+                    .with_context(|| format!("Failed to read {}", config_path))?;
+                previous_configs.push((config_path, contents));
+            }
+        }
+
+        let applied = (|| {
+            if metadata.grpc_url != previous.grpc_url {
+                updated.get_pcli_binary().set_grpc_url()?;
+                updated.get_pclientd_binary().set_grpc_url()?;
+            }
+
+            let pd_path = updated.pd_path();
+            if previous.client_only && !metadata.client_only {
+                if let Err(e) = updated.initialize_node(&self.cache, &self.config) {
+                    if fs::symlink_metadata(&pd_path).is_ok() {
+                        let _ = fs::remove_file(&pd_path);
+                    }
+                    return Err(e.context(format!("Failed to set up pd for {}", environment_alias)));
+                }
+            }
+            if !previous.client_only
+                && metadata.client_only
+                && fs::symlink_metadata(&pd_path).is_ok()
+            {
+                tracing::debug!("removing {}", pd_path);
+                fs::remove_file(&pd_path)?;
+            }
+
+            Ok(())
+        })();
+        if let Err(e) = applied {
+            restore_files(&previous_configs);
+            return Err(e);
+        }
+
+        let previous = environment.clone();
+        let updated = Arc::new(updated);
+        self.set_environment(environment_alias, updated.clone());
+        if let Err(e) = self.persist() {
+            restore_files(&previous_configs);
+            self.set_environment(environment_alias, previous);
+            return Err(e);
+        }
+
+        Ok(updated)
+    }
+
+    /// Returns the paths outside the environment that a node of the environment may have
+    /// been started from: a node started while the environment was active runs from the
+    /// global bin symlink.
+    fn global_node_binaries(&self, environment_alias: &str) -> Vec<Utf8PathBuf> {
+        let mut binaries = Vec::new();
+        if self
+            .active_environment
+            .as_ref()
+            .is_some_and(|e| e.metadata().alias == environment_alias)
+        {
+            binaries.push(self.home_dir.join("bin").join("pd"));
+        }
+        binaries
+    }
+
+    /// Replaces the stored environment with the given alias, including the active environment.
+    fn set_environment(&mut self, environment_alias: &str, environment: Arc<Environment>) {
         self.environments
             .retain(|e| e.metadata().alias != environment_alias);
 
-        // Update the stored environment within penv
         if let Some(active) = &self.active_environment {
             if active.as_ref().metadata().alias == environment_alias {
-                self.active_environment = Some(e.clone());
+                self.active_environment = Some(environment.clone());
             }
         }
 
-        self.environments.push(e.clone());
-
-        self.persist()?;
-
-        environment.remove_symlinks()?;
-        environment.create_symlinks(&self.cache)?;
-
-        Ok(())
+        self.environments.push(environment);
     }
 
     pub fn path_string(&self) -> String {
@@ -787,39 +1386,147 @@ impl Penv {
     pub fn pcli_home(&self) -> Option<Utf8PathBuf> {
         self.active_environment
             .as_ref()
-            .map(|environment| environment.path().join("pcli"))
+            .map(|environment| environment.pcli_home())
     }
 
     pub fn pclientd_home(&self) -> Option<Utf8PathBuf> {
         self.active_environment
             .as_ref()
-            .map(|environment| environment.path().join("pclientd"))
+            .map(|environment| environment.pclientd_home())
     }
 
     pub fn pd_home(&self) -> Option<Utf8PathBuf> {
-        self.active_environment.as_ref().map(|environment| {
-            environment
-                .path()
-                .join("network_data")
-                .join("node0")
-                .join("pd")
-        })
+        self.active_environment
+            .as_ref()
+            .map(|environment| environment.pd_home())
     }
 
-    // TODO: move to Environment trait
     pub fn cometbft_home(&self) -> Option<Utf8PathBuf> {
-        self.active_environment.as_ref().map(|environment| {
-            environment
-                .path()
-                .join("network_data")
-                .join("node0")
-                .join("cometbft")
+        self.active_environment
+            .as_ref()
+            .map(|environment| environment.cometbft_home())
+    }
+
+    /// Returns the environment in effect in the given directory and why: an environment
+    /// activated for the current shell takes precedence over a project file in the
+    /// directory or one of its ancestors, which takes precedence over the global
+    /// active environment.
+    pub fn effective_environment(
+        &self,
+        dir: &Utf8Path,
+    ) -> Result<Option<(Arc<Environment>, EnvironmentSource)>> {
+        if let Some(environment_alias) = shell_environment() {
+            let environment = self
+                .environments
+                .get_environment(&environment_alias)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Environment with alias {} selected by `penv shell` does not exist",
+                        environment_alias
+                    )
+                })?;
+
+            return Ok(Some((environment, EnvironmentSource::Shell)));
+        }
+
+        if let Some(project_file) = ProjectFile::discover(dir)? {
+            let environment = self
+                .environments
+                .get_environment(&project_file.environment_alias)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Environment with alias {} selected by {} does not exist",
+                        project_file.environment_alias,
+                        project_file.path
+                    )
+                })?;
+
+            return Ok(Some((
+                environment,
+                EnvironmentSource::ProjectFile(project_file.path),
+            )));
+        }
+
+        Ok(self
+            .active_environment
+            .clone()
+            .map(|environment| (environment, EnvironmentSource::Global)))
+    }
+}
+
+/// Finds the newest release matching the version requirement that has assets for the
+/// target architecture. A `latest` requirement resolves to the newest such release
+/// on the given channel; drafts are only considered on the `all` channel.
+fn latest_matching_release<'a>(
+    releases: &'a [Release],
+    version_requirement: &VersionReqOrLatest,
+    channel: ReleaseChannel,
+    target_arch: &Triple,
+) -> Option<&'a Release> {
+    let candidates = releases
+        .iter()
+        .filter(|release| {
+            // Typically a release should contain all assets for all architectures,
+            // but if it doesn't, this may produce unexpected failures.
+            //
+            // If the candidate release has no assets for the target architecture, skip it
+            let has_arch_asset = release
+                .assets
+                .iter()
+                .any(|asset| asset.target_arch.as_ref() == Some(target_arch));
+            if !has_arch_asset {
+                tracing::debug!(
+                    "skipping release {} because it has no assets for the target architecture",
+                    release.name
+                );
+            }
+            has_arch_asset
         })
+        .filter(|release| !release.draft || channel == ReleaseChannel::All)
+        .collect::<Vec<_>>();
+
+    let latest_version = &channel.latest_release(candidates.iter().copied())?.version;
+
+    candidates
+        .iter()
+        .filter(|release| version_requirement.matches(&release.version, latest_version))
+        .max()
+        .copied()
+}
+
+/// Prints the release notes of every release after `from` up to and including `to`.
+fn print_changelog(releases: &[Release], from: &Version, to: &Version) {
+    let mut releases = releases
+        .iter()
+        .filter(|r| &r.version > from && &r.version <= to)
+        .collect::<Vec<_>>();
+    releases.sort();
+
+    println!("changes from {} to {}:", from, to);
+    for release in releases {
+        println!();
+        println!("## {}", release.name);
+        println!();
+        match &release.body {
+            Some(body) if !body.trim().is_empty() => println!("{}", body.trim()),
+            _ => println!("no release notes available"),
+        }
+    }
+    println!();
+}
+
+/// Writes back the previous contents of files after a failed change, logging any failures
+/// rather than hiding the error that caused the change to fail.
+fn restore_files(files: &[(Utf8PathBuf, String)]) {
+    for (path, contents) in files {
+        if let Err(e) = write_atomic(path, contents) {
+            tracing::warn!("failed to restore {}: {}", path, e);
+        }
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use std::str::FromStr as _;
 
     use semver::Version;
@@ -828,13 +1535,87 @@ mod tests {
     use crate::penv::{
         cache::cache::CacheData,
         release::{
-            binary::InstalledBinaryRelease, git_repo::CheckoutMetadata, InstalledAsset,
+            binary::InstalledBinaryRelease, git_repo::CheckoutMetadata, Asset, InstalledAsset,
             VersionReqOrLatest,
         },
     };
 
     use super::*;
 
+    fn release(version: &str, target_arch: &str) -> Release {
+        Release {
+            version: Version::parse(version).unwrap(),
+            body: None,
+            assets: vec![Asset {
+                target_arch: Some(Triple::from_str(target_arch).unwrap()),
+                browser_download_url: format!("https://example.com/pcli-{target_arch}.tar.gz"),
+                expected_sha256sum: None,
+            }],
+            name: version.to_string(),
+            prerelease: false,
+            draft: false,
+        }
+    }
+
+    #[test]
+    fn latest_matching_release_for_target_arch() {
+        let linux = Triple::from_str("x86_64-unknown-linux-gnu").unwrap();
+        let releases = vec![
+            release("1.5.0", "x86_64-unknown-linux-gnu"),
+            release("1.5.2", "x86_64-unknown-linux-gnu"),
+            release("1.5.3", "aarch64-apple-darwin"),
+            release("2.0.0", "x86_64-unknown-linux-gnu"),
+        ];
+
+        let stable = ReleaseChannel::Stable;
+
+        let req = VersionReqOrLatest::VersionReq("1.5".parse().unwrap());
+        let latest = latest_matching_release(&releases, &req, stable, &linux).unwrap();
+        assert_eq!(latest.version, Version::new(1, 5, 2));
+
+        let latest =
+            latest_matching_release(&releases, &VersionReqOrLatest::Latest, stable, &linux);
+        assert_eq!(latest.unwrap().version, Version::new(2, 0, 0));
+
+        let req = VersionReqOrLatest::VersionReq("3".parse().unwrap());
+        assert!(latest_matching_release(&releases, &req, stable, &linux).is_none());
+    }
+
+    #[test]
+    fn latest_matching_release_on_channel() {
+        let linux = Triple::from_str("x86_64-unknown-linux-gnu").unwrap();
+        let releases = vec![
+            release("1.5.2", "x86_64-unknown-linux-gnu"),
+            release("2.0.0-alpha.1", "x86_64-unknown-linux-gnu"),
+            release("2.0.0", "aarch64-apple-darwin"),
+            // Flagged as a prerelease on GitHub despite a plain version
+            Release {
+                prerelease: true,
+                ..release("2.0.1", "x86_64-unknown-linux-gnu")
+            },
+            Release {
+                draft: true,
+                ..release("2.1.0", "x86_64-unknown-linux-gnu")
+            },
+        ];
+
+        let latest = VersionReqOrLatest::Latest;
+        let stable = latest_matching_release(&releases, &latest, ReleaseChannel::Stable, &linux);
+        assert_eq!(stable.unwrap().version, Version::new(1, 5, 2));
+
+        let prerelease =
+            latest_matching_release(&releases, &latest, ReleaseChannel::Prerelease, &linux);
+        assert_eq!(prerelease.unwrap().version, Version::new(2, 0, 1));
+
+        let all = latest_matching_release(&releases, &latest, ReleaseChannel::All, &linux);
+        assert_eq!(all.unwrap().version, Version::new(2, 1, 0));
+
+        // Drafts are never matched by a version requirement outside the `all` channel
+        let req = VersionReqOrLatest::VersionReq("2".parse().unwrap());
+        let stable = latest_matching_release(&releases, &req, ReleaseChannel::Stable, &linux);
+        assert_eq!(stable.unwrap().version, Version::new(2, 0, 1));
+    }
+
     #[test]
     fn deserialize_penv() {
         let cache_data = CacheData {
@@ -845,6 +1626,7 @@ mod tests {
                     install_path: "/tmp/test".into(),
                 }),
                 InstalledRelease::Binary(InstalledBinaryRelease {
+                    repository_name: "test/test".into(),
                     version: Version::parse("1.0.0").unwrap(),
                     body: Some("Release notes for version 1.0.0".to_string()),
                     assets: vec![InstalledAsset {
@@ -861,9 +1643,11 @@ mod tests {
                 data: cache_data,
                 home: "/tmp/test".into(),
             },
-            downloader: Downloader::new("test/test".into()).expect("test downloader"),
-            repository_name: "test".into(),
+            downloaders: Mutex::new(HashMap::new()),
+            tag_patterns: BTreeMap::new(),
+            config: Config::default(),
             home_dir: "/tmp/test".into(),
+            previous_home: None,
             active_environment: Some(Arc::new(Environment::CheckoutEnvironment(
                 CheckoutEnvironment {
                     metadata: EnvironmentMetadata {
@@ -907,8 +1691,11 @@ mod tests {
                             pd_join_url: Url::parse("http://localhost:50051").unwrap(),
                             generate_network: false,
                         },
+                        repository_name: "test/test".into(),
                         version_requirement: VersionReqOrLatest::Latest,
                         pinned_version: Version::parse("1.0.0").unwrap(),
+                        channel: ReleaseChannel::Stable,
+                        pin_history: vec![],
                     })),
                 ],
             },
@@ -919,29 +1706,8 @@ mod tests {
 
         // Example TOML string for deserialization
         let toml_str = r#"
-            repository_name = "test"
             home_dir = "/tmp/test"
             active_environment = "test"
-            [[cache.installed_releases]]
-            type = "GitCheckout"
-
-            [cache.installed_releases.args]
-            name = "test"
-            url = "http://localhost:50051"
-            install_path = "/tmp/test"
-
-            [[cache.installed_releases]]
-            type = "Binary"
-
-            [cache.installed_releases.args]
-            version = "1.0.0"
-            body = "Release notes for version 1.0.0"
-            name = "Release 1.0.0"
-            root_dir = "/tmp/fake"
-
-            [[cache.installed_releases.args.assets]]
-            target_arch = "x86_64-unknown-linux-gnu"
-            local_filepath = "/tmp/fake"
             [[environments.environments]]
             type = "CheckoutEnvironment"
             [environments.environments.args.metadata]
@@ -961,6 +1727,7 @@ mod tests {
             type = "BinaryEnvironment"
 
             [environments.environments.args]
+            repository_name = "test/test"
             pinned_version = "1.0.0"
 
             [environments.environments.args.version_requirement]
@@ -980,4 +1747,279 @@ mod tests {
         assert!(penv.environments.len() == 2);
         assert!(penv.active_environment.is_some());
     }
+
+    /// Creates a penv home with version 1.0.0 of stub binaries installed, a binary
+    /// environment "binary" and a checkout environment "checkout", both with a node.
+    pub(crate) fn test_penv(home: &Utf8Path) -> Penv {
+        let version_dir = home.join("versions/1.0.0");
+        fs::create_dir_all(version_dir.join("bin")).unwrap();
+        let mut assets = Vec::new();
+        for binary in ["pcli", "pclientd", "pd"] {
+            let path = version_dir.join("bin").join(binary);
+            fs::write(&path, "#!/bin/sh\nexit 0\n").unwrap();
+            #[cfg(target_family = "unix")]
+            {
+                use std::os::unix::fs::PermissionsExt as _;
+                fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            }
+            assets.push(InstalledAsset {
+                target_arch: Triple::host(),
+                local_filepath: path,
+            });
+        }
+
+        let metadata = |alias: &str| EnvironmentMetadata {
+            alias: alias.into(),
+            grpc_url: Url::parse("http://localhost:8080").unwrap(),
+            root_dir: home.join("environments").join(alias),
+            client_only: false,
+            pd_join_url: Url::parse("http://localhost:26657").unwrap(),
+            generate_network: false,
+        };
+        let binary = Environment::BinaryEnvironment(BinaryEnvironment {
+            metadata: metadata("binary"),
+            repository_name: "test/test".into(),
+            version_requirement: VersionReqOrLatest::Latest,
+            pinned_version: Version::new(1, 0, 0),
+            channel: ReleaseChannel::Stable,
+            pin_history: vec![],
+        });
+        let checkout = Environment::CheckoutEnvironment(CheckoutEnvironment {
+            metadata: metadata("checkout"),
+            git_checkout: Arc::new(CheckoutMetadata {
+                name: "checkout".into(),
+                url: "https://example.com/penumbra.git".into(),
+                install_path: home.join("checkouts/checkout"),
+            }),
+        });
+
+        let penv = Penv {
+            cache: Cache {
+                data: CacheData {
+                    installed_releases: vec![InstalledRelease::Binary(InstalledBinaryRelease {
+                        repository_name: "test/test".into(),
+                        version: Version::new(1, 0, 0),
+                        body: Some("notes".into()),
+                        assets,
+                        name: "1.0.0".into(),
+                        root_dir: version_dir,
+                    })],
+                },
+                home: home.to_owned(),
+            },
+            downloaders: Mutex::new(HashMap::new()),
+            tag_patterns: BTreeMap::new(),
+            config: Config::default(),
+            home_dir: home.to_owned(),
+            previous_home: None,
+            active_environment: None,
+            environments: Environments {
+                environments: vec![Arc::new(binary), Arc::new(checkout)],
+            },
+        };
+
+        for environment in penv.environments.iter() {
+            fs::create_dir_all(environment.bin_dir()).unwrap();
+            for dir in [environment.pcli_home(), environment.pclientd_home()] {
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(
+                    dir.join("config.toml"),
+                    format!(
+                        "grpc_url = \"http://localhost:8080/\"\nhome = \"{}\"\n",
+                        dir
+                    ),
+                )
+                .unwrap();
+            }
+            match environment.as_ref() {
+                Environment::BinaryEnvironment(env) => env.create_symlinks(&penv.cache).unwrap(),
+                Environment::CheckoutEnvironment(env) => env.write_wrappers().unwrap(),
+            }
+        }
+        penv.persist().unwrap();
+
+        penv
+    }
+
+    #[test]
+    fn relocate_home() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let old_home = dir.join("penv");
+        let new_home = dir.join("moved");
+        test_penv(&old_home);
+
+        // A config referring to a sibling of the home that only shares its prefix.
+        let pcli_config = old_home.join("environments/binary/pcli/config.toml");
+        let sibling = format!("{}-old/keys", old_home);
+        let mut contents = fs::read_to_string(&pcli_config).unwrap();
+        contents.push_str(&format!("backup = \"{}\"\n", sibling));
+        fs::write(&pcli_config, contents).unwrap();
+
+        fs::rename(&old_home, &new_home).unwrap();
+        let mut penv = Penv::new(new_home.clone()).unwrap();
+        assert_eq!(penv.previous_home.as_ref(), Some(&old_home));
+        penv.finish_relocation().unwrap();
+
+        let config =
+            fs::read_to_string(new_home.join("environments/binary/pcli/config.toml")).unwrap();
+        assert!(config.contains(&format!(
+            "home = \"{}\"",
+            new_home.join("environments/binary/pcli")
+        )));
+        assert!(config.contains(&format!("backup = \"{}\"", sibling)));
+
+        let link = fs::read_link(new_home.join("environments/binary/bin/pd")).unwrap();
+        assert_eq!(link, new_home.join("versions/1.0.0/bin/pd"));
+
+        for binary in ["pcli", "pclientd", "pd"] {
+            let wrapper =
+                fs::read_to_string(new_home.join("environments/checkout/bin").join(binary))
+                    .unwrap();
+            assert!(wrapper.contains(new_home.join("environments/checkout/checkout").as_str()));
+            assert!(!wrapper.contains(old_home.as_str()));
+        }
+
+        let penv = Penv::new(new_home.clone()).unwrap();
+        assert!(penv.previous_home.is_none());
+    }
+
+    #[test]
+    fn edit_environment() {
+        let home = tempfile::tempdir().unwrap();
+        let home = Utf8Path::from_path(home.path()).unwrap();
+        let mut penv = test_penv(home);
+        let env_dir = home.join("environments/binary");
+        let grpc_url = |binary: &str| {
+            let config: toml::Table = toml::from_str(
+                &fs::read_to_string(env_dir.join(binary).join("config.toml")).unwrap(),
+            )
+            .unwrap();
+            config["grpc_url"].as_str().unwrap().to_string()
+        };
+
+        let same_url = Url::parse("http://localhost:8080").unwrap();
+        let e = penv
+            .edit_environment("binary", Some(same_url), None, Some(false))
+            .unwrap_err();
+        assert!(e.to_string().contains("already has these settings"));
+
+        // A failure rewriting the pclientd config restores the pcli config.
+        let pclientd_config = env_dir.join("pclientd/config.toml");
+        fs::write(&pclientd_config, "not toml").unwrap();
+        let new_url = Url::parse("https://grpc.testnet.penumbra.zone").unwrap();
+        penv.edit_environment("binary", Some(new_url.clone()), None, None)
+            .unwrap_err();
+        assert_eq!(grpc_url("pcli"), "http://localhost:8080/");
+        assert_eq!(fs::read_to_string(&pclientd_config).unwrap(), "not toml");
+
+        fs::write(&pclientd_config, "grpc_url = \"http://localhost:8080/\"\n").unwrap();
+        penv.edit_environment("binary", Some(new_url.clone()), None, None)
+            .unwrap();
+        assert_eq!(grpc_url("pcli"), new_url.as_str());
+        assert_eq!(grpc_url("pclientd"), new_url.as_str());
+
+        // Switching to client-only removes pd, and switching back sets it up again.
+        let pd_path = env_dir.join("bin/pd");
+        penv.edit_environment("binary", None, None, Some(true))
+            .unwrap();
+        assert!(fs::symlink_metadata(&pd_path).is_err());
+        let penv_reloaded = Penv::new(home.to_owned()).unwrap();
+        let environment = penv_reloaded
+            .environments
+            .get_environment("binary")
+            .unwrap();
+        assert!(environment.metadata().client_only);
+        assert_eq!(environment.metadata().grpc_url, new_url);
+
+        penv.edit_environment("binary", None, None, Some(false))
+            .unwrap();
+        assert_eq!(
+            fs::read_link(&pd_path).unwrap(),
+            home.join("versions/1.0.0/bin/pd")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn migrate_rollback() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let home = tempfile::tempdir().unwrap();
+        let home = Utf8Path::from_path(home.path()).unwrap();
+        let mut penv = test_penv(home);
+
+        // A new pd that damages the node state before failing to migrate it.
+        let version_dir = home.join("versions/2.0.0");
+        fs::create_dir_all(version_dir.join("bin")).unwrap();
+        let mut assets = Vec::new();
+        for binary in ["pcli", "pclientd", "pd"] {
+            let path = version_dir.join("bin").join(binary);
+            let script = match binary {
+                "pd" => "#!/bin/sh\nif [ \"$1\" = migrate ]; then\n  echo damaged > \"$3/state\"\n  echo 'migration failed' >&2\n  exit 1\nfi\nexit 0\n",
+                _ => "#!/bin/sh\nexit 0\n",
+            };
+            fs::write(&path, script).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            assets.push(InstalledAsset {
+                target_arch: Triple::host(),
+                local_filepath: path,
+            });
+        }
+        penv.cache
+            .data
+            .installed_releases
+            .push(InstalledRelease::Binary(InstalledBinaryRelease {
+                repository_name: "test/test".into(),
+                version: Version::new(2, 0, 0),
+                body: Some("notes".into()),
+                assets,
+                name: "2.0.0".into(),
+                root_dir: version_dir.clone(),
+            }));
+        penv.persist().unwrap();
+        penv.repin_environment("binary", |e| {
+            e.version_requirement = VersionReqOrLatest::from_str("^1").unwrap();
+            Ok(())
+        })
+        .unwrap();
+
+        let env_dir = home.join("environments/binary");
+        let pd_home = env_dir.join("network_data/node0/pd");
+        fs::create_dir_all(&pd_home).unwrap();
+        fs::write(pd_home.join("state"), "before").unwrap();
+
+        let e = penv
+            .migrate_environment("binary".into(), Version::new(2, 0, 0))
+            .unwrap_err();
+        assert!(format!("{:#}", e).contains("migration failed"));
+
+        // The node state and the pinned version are restored, and the backup cleared so
+        // that the migration can be retried.
+        assert_eq!(fs::read_to_string(pd_home.join("state")).unwrap(), "before");
+        assert!(!env_dir.join("backups/network_data-1.0.0").exists());
+        assert!(!env_dir.join("backups/export-1.0.0").exists());
+        assert_eq!(
+            fs::read_link(env_dir.join("bin/pd")).unwrap(),
+            home.join("versions/1.0.0/bin/pd")
+        );
+        let pinned = |home: &Utf8Path| {
+            let penv = Penv::new(home.to_owned()).unwrap();
+            match *penv.environments.get_environment("binary").unwrap() {
+                Environment::BinaryEnvironment(ref env) => (
+                    env.pinned_version.to_string(),
+                    env.version_requirement.to_string(),
+                ),
+                Environment::CheckoutEnvironment(_) => unreachable!(),
+            }
+        };
+        assert_eq!(pinned(home), ("1.0.0".to_string(), "^1".to_string()));
+
+        // A successful migration past the requirement replaces it.
+        fs::write(version_dir.join("bin/pd"), "#!/bin/sh\nexit 0\n").unwrap();
+        let mut penv = Penv::new(home.to_owned()).unwrap();
+        penv.migrate_environment("binary".into(), Version::new(2, 0, 0))
+            .unwrap();
+        assert_eq!(pinned(home), ("2.0.0".to_string(), "^2.0.0".to_string()));
+    }
 }
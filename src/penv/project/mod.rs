@@ -0,0 +1,3 @@
+mod project;
+
+pub use project::*;
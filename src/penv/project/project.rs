@@ -0,0 +1,151 @@
+use std::{
+    fmt::{self, Display},
+    fs,
+};
+
+use anyhow::{anyhow, Context as _, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Deserialize;
+
+/// The names of the files that select an environment for a directory tree,
+/// in order of precedence within a single directory.
+pub const PROJECT_FILE_NAMES: [&str; 2] = [".penv.toml", ".penv-environment"];
+
+/// A file selecting the environment to use within the directory tree containing it.
+///
+/// `.penv.toml` files contain an `environment = "<alias>"` key, while
+/// `.penv-environment` files contain just the alias.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ProjectFile {
+    pub path: Utf8PathBuf,
+    pub environment_alias: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProjectFileToml {
+    environment: String,
+}
+
+impl ProjectFile {
+    /// Finds and reads the nearest project file in `dir` or any of its ancestors.
+    pub fn discover(dir: &Utf8Path) -> Result<Option<Self>> {
+        Self::find(dir).map(Self::read).transpose()
+    }
+
+    /// Returns the path of the nearest project file in `dir` or any of its ancestors,
+    /// without reading it.
+    pub fn find(dir: &Utf8Path) -> Option<Utf8PathBuf> {
+        dir.ancestors()
+            .flat_map(|dir| PROJECT_FILE_NAMES.map(|file_name| dir.join(file_name)))
+            .find(|path| path.is_file())
+    }
+
+    /// Reads the environment alias from a project file.
+    pub fn read(path: Utf8PathBuf) -> Result<Self> {
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read project file {}", path))?;
+
+        let environment_alias = if path.extension() == Some("toml") {
+            toml::from_str::<ProjectFileToml>(&contents)
+                .with_context(|| format!("Failed to parse project file {}", path))?
+                .environment
+        } else {
+            contents.lines().next().unwrap_or_default().to_string()
+        };
+
+        let environment_alias = environment_alias.trim().to_string();
+        if environment_alias.is_empty() {
+            return Err(anyhow!(
+                "Project file {} does not name an environment",
+                path
+            ));
+        }
+
+        Ok(Self {
+            path,
+            environment_alias,
+        })
+    }
+}
+
+/// Returns the current working directory, which project files are discovered from.
+pub fn current_dir() -> Result<Utf8PathBuf> {
+    let dir = std::env::current_dir().context("Failed to determine the current directory")?;
+
+    Utf8PathBuf::from_path_buf(dir)
+        .map_err(|dir| anyhow!("Current directory {} is not valid UTF-8", dir.display()))
+}
+
+/// The variable holding the alias of the environment activated for a single shell
+/// with `penv shell`.
+pub const SHELL_ENVIRONMENT_VAR: &str = "PENV_SHELL_ENVIRONMENT";
+
+/// Returns the alias of the environment activated for the current shell, if any.
+pub fn shell_environment() -> Option<String> {
+    std::env::var(SHELL_ENVIRONMENT_VAR)
+        .ok()
+        .filter(|alias| !alias.is_empty())
+}
+
+/// Why an environment is in effect.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum EnvironmentSource {
+    /// Activated for the current shell only with `penv shell`.
+    Shell,
+    /// Selected by a project file in the current directory tree.
+    ProjectFile(Utf8PathBuf),
+    /// Activated globally with `penv use`.
+    Global,
+}
+
+impl EnvironmentSource {
+    /// The kind of source, one of `shell`, `project` and `global`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EnvironmentSource::Shell => "shell",
+            EnvironmentSource::ProjectFile(_) => "project",
+            EnvironmentSource::Global => "global",
+        }
+    }
+}
+
+impl Display for EnvironmentSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvironmentSource::Shell => write!(f, "set by `penv shell`"),
+            EnvironmentSource::ProjectFile(path) => write!(f, "set by {}", path),
+            EnvironmentSource::Global => write!(f, "set by `penv use`"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discover_project_file() {
+        let root = tempfile::tempdir().unwrap();
+        let root = Utf8PathBuf::from_path_buf(root.path().to_path_buf()).unwrap();
+        let nested = root.join("repo/crates/app");
+        fs::create_dir_all(&nested).unwrap();
+
+        assert_eq!(ProjectFile::discover(&nested).unwrap(), None);
+
+        fs::write(root.join("repo/.penv-environment"), "testnet\n").unwrap();
+        let found = ProjectFile::discover(&nested).unwrap().unwrap();
+        assert_eq!(found.environment_alias, "testnet");
+        assert_eq!(found.path, root.join("repo/.penv-environment"));
+
+        // `.penv.toml` takes precedence within a directory, and nearer files win
+        fs::write(root.join("repo/.penv.toml"), "environment = \"devnet\"\n").unwrap();
+        fs::write(root.join("repo/crates/.penv-environment"), "mainnet").unwrap();
+        let found = ProjectFile::discover(&nested).unwrap().unwrap();
+        assert_eq!(found.environment_alias, "mainnet");
+        let found = ProjectFile::discover(&root.join("repo")).unwrap().unwrap();
+        assert_eq!(found.environment_alias, "devnet");
+
+        fs::write(root.join("repo/crates/.penv-environment"), "\n").unwrap();
+        assert!(ProjectFile::discover(&nested).is_err());
+    }
+}
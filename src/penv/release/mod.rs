@@ -1,7 +1,9 @@
 mod asset;
 mod release;
+mod tag;
 mod version;
 
 pub(crate) use asset::*;
 pub(crate) use release::*;
+pub(crate) use tag::*;
 pub(crate) use version::*;
@@ -1,6 +1,6 @@
 use std::fmt::{self, Display};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use binary::InstalledBinaryRelease;
 use camino::Utf8PathBuf;
 use git_repo::{CheckoutMetadata, RepoMetadata};
@@ -8,23 +8,30 @@ use semver::Version;
 use serde::{Deserialize, Serialize};
 use target_lexicon::Triple;
 
-use super::{Asset, InstalledAsset, RawAsset, RepoOrVersion};
+use super::{Asset, InstalledAsset, RawAsset, RepoOrVersion, TagPattern};
 
 pub(crate) mod binary;
 pub(crate) mod git_repo;
 
+/// The GitHub repository releases are fetched from unless another one is given.
+pub const DEFAULT_REPOSITORY_NAME: &str = "penumbra-zone/penumbra";
+
 /// Release information as deserialized from the GitHub API JSON,
 /// prior to enriching.
 #[derive(Clone, Debug, Deserialize)]
 pub(crate) struct RawRelease {
     tag_name: String,
-    name: String,
+    pub(crate) name: String,
     body: Option<String>,
     assets: Vec<RawAsset>,
+    #[serde(default)]
+    prerelease: bool,
+    #[serde(default)]
+    draft: bool,
 }
 
 /// Release information enriched with proper domain types.
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
 pub struct Release {
     /// The version of the release, parsed as semver.
     pub version: Version,
@@ -34,6 +41,18 @@ pub struct Release {
     pub assets: Vec<Asset>,
     /// The name of the release on GitHub.
     pub name: String,
+    /// Whether the release is marked as a prerelease on GitHub.
+    pub prerelease: bool,
+    /// Whether the release is an unpublished draft on GitHub.
+    pub draft: bool,
+}
+
+impl Release {
+    /// Whether the release is a prerelease, either because it's flagged as one on GitHub
+    /// or because its version has a prerelease component.
+    pub fn is_prerelease(&self) -> bool {
+        self.prerelease || !self.version.pre.is_empty()
+    }
 }
 
 impl Display for Release {
@@ -72,11 +91,15 @@ pub enum InstalledRelease {
 }
 
 impl InstalledRelease {
-    pub fn matches(&self, repo_or_version: &RepoOrVersion) -> bool {
+    /// Whether this is the given version from the GitHub repository `repository_name`,
+    /// or a checkout of the given git repo.
+    pub fn matches(&self, repository_name: &str, repo_or_version: &RepoOrVersion) -> bool {
         match self {
             InstalledRelease::Binary(release) => match repo_or_version {
                 RepoOrVersion::Repo(_) => false,
-                RepoOrVersion::Version(version) => release.version == *version,
+                RepoOrVersion::Version(version) => {
+                    release.repository_name == repository_name && release.version == *version
+                }
             },
             InstalledRelease::GitCheckout(checkout) => match repo_or_version {
                 RepoOrVersion::Repo(repo) => checkout.url == *repo,
@@ -95,29 +118,18 @@ impl Display for InstalledRelease {
     }
 }
 
-impl TryInto<Release> for RawRelease {
-    type Error = anyhow::Error;
-
-    fn try_into(self) -> Result<Release> {
-        let version = Version::parse(&self.tag_name[1..])?;
-        Ok(Release {
-            version,
-            body: self.body,
-            assets: self
-                .assets
-                .into_iter()
-                .map(|a| a.try_into())
-                .collect::<Result<_>>()?,
-            name: self.name,
-        })
-    }
-}
-
-impl TryInto<Release> for &RawRelease {
-    type Error = anyhow::Error;
+impl RawRelease {
+    /// Enriches the release with proper domain types, using the tag pattern
+    /// to extract the version from the release's tag.
+    pub(crate) fn try_into_release(&self, tag_pattern: &TagPattern) -> Result<Release> {
+        let version = tag_pattern.parse_version(&self.tag_name).ok_or_else(|| {
+            anyhow!(
+                "tag {} does not match tag pattern {}",
+                self.tag_name,
+                tag_pattern
+            )
+        })?;
 
-    fn try_into(self) -> Result<Release> {
-        let version = Version::parse(&self.tag_name[1..])?;
         Ok(Release {
             version,
             body: self.body.clone(),
@@ -128,6 +140,8 @@ impl TryInto<Release> for &RawRelease {
                 .map(|a| a.try_into())
                 .collect::<Result<_>>()?,
             name: self.name.clone(),
+            prerelease: self.prerelease,
+            draft: self.draft,
         })
     }
 }
@@ -197,6 +211,8 @@ impl Display for InstallableRelease {
 
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) struct InstallableBinaryRelease {
+    /// The GitHub repository the release was downloaded from.
+    pub(crate) repository_name: String,
     pub(crate) pcli: Option<Utf8PathBuf>,
     pub(crate) pclientd: Option<Utf8PathBuf>,
     pub(crate) pd: Option<Utf8PathBuf>,
@@ -18,6 +18,8 @@ use super::{Installable, InstallableBinaryRelease, UsableRelease};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct InstalledBinaryRelease {
+    /// The GitHub repository the release was downloaded from, e.g. "penumbra-zone/penumbra".
+    pub repository_name: String,
     /// The version of the release, parsed as semver.
     pub version: Version,
     /// The markdown formatted release notes.
@@ -41,7 +43,8 @@ impl Serialize for InstalledBinaryRelease {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("InstalledRelease", 4)?;
+        let mut state = serializer.serialize_struct("InstalledRelease", 6)?;
+        state.serialize_field("repository_name", &self.repository_name)?;
         state.serialize_field("version", &self.version)?;
         state.serialize_field("body", &self.body)?;
         state.serialize_field("name", &self.name)?;
@@ -57,6 +60,7 @@ impl<'de> Deserialize<'de> for InstalledBinaryRelease {
         D: Deserializer<'de>,
     {
         enum Field {
+            RepositoryName,
             Version,
             Body,
             Assets,
@@ -75,7 +79,9 @@ impl<'de> Deserialize<'de> for InstalledBinaryRelease {
                     type Value = Field;
 
                     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                        formatter.write_str("`version`, `body`, `assets`, `root_dir`, or `name`")
+                        formatter.write_str(
+                            "`repository_name`, `version`, `body`, `assets`, `root_dir`, or `name`",
+                        )
                     }
 
                     fn visit_str<E>(self, value: &str) -> Result<Field, E>
@@ -83,6 +89,7 @@ impl<'de> Deserialize<'de> for InstalledBinaryRelease {
                         E: de::Error,
                     {
                         match value {
+                            "repository_name" => Ok(Field::RepositoryName),
                             "version" => Ok(Field::Version),
                             "body" => Ok(Field::Body),
                             "assets" => Ok(Field::Assets),
@@ -110,6 +117,7 @@ impl<'de> Deserialize<'de> for InstalledBinaryRelease {
             where
                 V: MapAccess<'de>,
             {
+                let mut repository_name = None;
                 let mut version = None;
                 let mut body = None;
                 let mut assets = None;
@@ -118,6 +126,12 @@ impl<'de> Deserialize<'de> for InstalledBinaryRelease {
 
                 while let Some(key) = map.next_key()? {
                     match key {
+                        Field::RepositoryName => {
+                            if repository_name.is_some() {
+                                return Err(de::Error::duplicate_field("repository_name"));
+                            }
+                            repository_name = Some(map.next_value()?);
+                        }
                         Field::Version => {
                             if version.is_some() {
                                 return Err(de::Error::duplicate_field("version"));
@@ -151,6 +165,8 @@ impl<'de> Deserialize<'de> for InstalledBinaryRelease {
                     }
                 }
 
+                let repository_name =
+                    repository_name.ok_or_else(|| de::Error::missing_field("repository_name"))?;
                 let version = version.ok_or_else(|| de::Error::missing_field("version"))?;
                 let body = body.ok_or_else(|| de::Error::missing_field("body"))?;
                 let assets = assets.ok_or_else(|| de::Error::missing_field("assets"))?;
@@ -158,6 +174,7 @@ impl<'de> Deserialize<'de> for InstalledBinaryRelease {
                 let root_dir = root_dir.ok_or_else(|| de::Error::missing_field("root_dir"))?;
 
                 Ok(InstalledBinaryRelease {
+                    repository_name,
                     version,
                     body,
                     assets,
@@ -167,7 +184,14 @@ impl<'de> Deserialize<'de> for InstalledBinaryRelease {
             }
         }
 
-        const FIELDS: &[&str] = &["version", "body", "assets", "name", "root_dir"];
+        const FIELDS: &[&str] = &[
+            "repository_name",
+            "version",
+            "body",
+            "assets",
+            "name",
+            "root_dir",
+        ];
         deserializer.deserialize_struct(
             "InstalledBinaryRelease",
             FIELDS,
@@ -249,6 +273,7 @@ impl Installable for InstallableBinaryRelease {
         });
 
         Ok(InstalledRelease::Binary(InstalledBinaryRelease {
+            repository_name: self.repository_name.clone(),
             version: self.version().clone(),
             body: self.release.body.clone(),
             assets: installed_assets,
@@ -0,0 +1,109 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+
+/// The placeholder for the version within a [`TagPattern`].
+const VERSION_PLACEHOLDER: &str = "{version}";
+
+/// Describes how the release tags of a repository map to versions,
+/// e.g. `v{version}` for `v1.5.2` or `penumbra-{version}` for `penumbra-1.5.2`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TagPattern {
+    prefix: String,
+    suffix: String,
+}
+
+impl TagPattern {
+    /// Extracts the version from a release tag, if the tag matches the pattern
+    /// and the remainder is a valid semver version.
+    pub fn parse_version(&self, tag: &str) -> Option<Version> {
+        let version = tag.strip_prefix(&self.prefix)?.strip_suffix(&self.suffix)?;
+
+        Version::parse(version).ok()
+    }
+}
+
+impl Default for TagPattern {
+    fn default() -> Self {
+        Self {
+            prefix: "v".to_string(),
+            suffix: String::new(),
+        }
+    }
+}
+
+impl FromStr for TagPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(VERSION_PLACEHOLDER) {
+            Some((prefix, suffix)) if !suffix.contains(VERSION_PLACEHOLDER) => Ok(Self {
+                prefix: prefix.to_string(),
+                suffix: suffix.to_string(),
+            }),
+            _ => Err(anyhow!(
+                "Tag pattern {} must contain {} exactly once",
+                s,
+                VERSION_PLACEHOLDER
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for TagPattern {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<TagPattern> for String {
+    fn from(value: TagPattern) -> Self {
+        value.to_string()
+    }
+}
+
+impl Display for TagPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}{}", self.prefix, VERSION_PLACEHOLDER, self.suffix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+
+    use super::TagPattern;
+
+    #[test]
+    fn parse_tags() {
+        let default = TagPattern::default();
+        assert_eq!(default.to_string(), "v{version}");
+        assert_eq!(default.parse_version("v1.5.2"), Some(Version::new(1, 5, 2)));
+        assert_eq!(
+            default.parse_version("v2.0.0-alpha.11"),
+            Some(Version::parse("2.0.0-alpha.11").unwrap())
+        );
+        assert_eq!(default.parse_version("1.5.2"), None);
+        assert_eq!(default.parse_version("testnet-preview"), None);
+        assert_eq!(default.parse_version("ü1.5.2"), None);
+        assert_eq!(default.parse_version(""), None);
+
+        let prefixed: TagPattern = "penumbra-{version}".parse().unwrap();
+        assert_eq!(
+            prefixed.parse_version("penumbra-1.5.2"),
+            Some(Version::new(1, 5, 2))
+        );
+        assert_eq!(prefixed.parse_version("v1.5.2"), None);
+
+        assert!("v{version}-{version}".parse::<TagPattern>().is_err());
+        assert!("v1.5.2".parse::<TagPattern>().is_err());
+    }
+}
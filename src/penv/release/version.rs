@@ -8,6 +8,8 @@ use std::{
 };
 use target_lexicon::Triple;
 
+use super::Release;
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum RepoOrVersion {
     Version(Version),
@@ -134,6 +136,108 @@ impl Display for VersionReqOrLatest {
     }
 }
 
+/// Which releases are considered when resolving `latest` or listing available releases.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReleaseChannel {
+    /// Only stable releases.
+    #[default]
+    Stable,
+    /// Stable releases and prereleases.
+    Prerelease,
+    /// Every release, including drafts.
+    All,
+}
+
+impl ReleaseChannel {
+    /// Whether the given version is published on this channel.
+    ///
+    /// Only the version is known for installed releases, so prereleases are
+    /// identified by their prerelease component.
+    pub fn includes(&self, version: &Version) -> bool {
+        match self {
+            ReleaseChannel::Stable => version.pre.is_empty(),
+            ReleaseChannel::Prerelease | ReleaseChannel::All => true,
+        }
+    }
+
+    /// Whether the given upstream release is published on this channel.
+    pub fn includes_release(&self, release: &Release) -> bool {
+        match self {
+            ReleaseChannel::Stable => !release.draft && !release.is_prerelease(),
+            ReleaseChannel::Prerelease => !release.draft,
+            ReleaseChannel::All => true,
+        }
+    }
+
+    /// Returns the newest of the given versions published on this channel.
+    pub fn latest<'a>(
+        &self,
+        versions: impl IntoIterator<Item = &'a Version>,
+    ) -> Option<&'a Version> {
+        versions.into_iter().filter(|v| self.includes(v)).max()
+    }
+
+    /// Returns the newest of the given upstream releases published on this channel.
+    pub fn latest_release<'a>(
+        &self,
+        releases: impl IntoIterator<Item = &'a Release>,
+    ) -> Option<&'a Release> {
+        releases
+            .into_iter()
+            .filter(|r| self.includes_release(r))
+            .max()
+    }
+}
+
+impl Display for ReleaseChannel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReleaseChannel::Stable => write!(f, "stable"),
+            ReleaseChannel::Prerelease => write!(f, "prerelease"),
+            ReleaseChannel::All => write!(f, "all"),
+        }
+    }
+}
+
+/// The kind of difference between two versions, as reported by `penv outdated`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionDrift {
+    Prerelease,
+    Patch,
+    Minor,
+    Major,
+}
+
+impl VersionDrift {
+    /// Classifies how far `current` is behind `newer`, if at all.
+    pub fn between(current: &Version, newer: &Version) -> Option<Self> {
+        if newer <= current {
+            None
+        } else if newer.major != current.major {
+            Some(VersionDrift::Major)
+        } else if newer.minor != current.minor {
+            Some(VersionDrift::Minor)
+        } else if newer.patch != current.patch {
+            Some(VersionDrift::Patch)
+        } else {
+            Some(VersionDrift::Prerelease)
+        }
+    }
+}
+
+impl Display for VersionDrift {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VersionDrift::Prerelease => write!(f, "prerelease"),
+            VersionDrift::Patch => write!(f, "patch"),
+            VersionDrift::Minor => write!(f, "minor"),
+            VersionDrift::Major => write!(f, "major"),
+        }
+    }
+}
+
 pub(crate) fn extract_triple(filename: &str) -> Option<Triple> {
     // Define the regex pattern to capture the target triple part of the filename
     let re = Regex::new(r"-([a-zA-Z0-9_-]+)-([a-zA-Z0-9_-]+)-([a-zA-Z0-9_-]+)\.").unwrap();
@@ -160,7 +264,51 @@ pub(crate) fn extract_triple(filename: &str) -> Option<Triple> {
 
 #[cfg(test)]
 mod tests {
-    use crate::penv::release::VersionReqOrLatest;
+    use semver::Version;
+
+    use crate::penv::release::{ReleaseChannel, VersionDrift, VersionReqOrLatest};
+
+    #[test]
+    fn latest_on_channel() {
+        let versions = ["0.79.1", "0.80.0", "0.81.0-alpha.1"]
+            .iter()
+            .map(|s| Version::parse(s).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            ReleaseChannel::Stable.latest(&versions),
+            Some(&Version::parse("0.80.0").unwrap())
+        );
+        assert_eq!(
+            ReleaseChannel::Prerelease.latest(&versions),
+            Some(&Version::parse("0.81.0-alpha.1").unwrap())
+        );
+        assert_eq!(ReleaseChannel::Stable.latest(&versions[2..]), None);
+    }
+
+    #[test]
+    fn version_drift() {
+        let v = |s: &str| Version::parse(s).unwrap();
+
+        assert_eq!(VersionDrift::between(&v("1.5.2"), &v("1.5.2")), None);
+        assert_eq!(VersionDrift::between(&v("1.5.2"), &v("1.5.1")), None);
+        assert_eq!(
+            VersionDrift::between(&v("1.5.1"), &v("1.5.2")),
+            Some(VersionDrift::Patch)
+        );
+        assert_eq!(
+            VersionDrift::between(&v("1.4.9"), &v("1.5.0")),
+            Some(VersionDrift::Minor)
+        );
+        assert_eq!(
+            VersionDrift::between(&v("1.5.2"), &v("2.0.0-alpha.11")),
+            Some(VersionDrift::Major)
+        );
+        assert_eq!(
+            VersionDrift::between(&v("2.0.0-alpha.10"), &v("2.0.0-alpha.11")),
+            Some(VersionDrift::Prerelease)
+        );
+    }
 
     #[test]
     fn deserialize_version() {
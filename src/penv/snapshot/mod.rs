@@ -0,0 +1,3 @@
+mod snapshot;
+
+pub use snapshot::*;
@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use camino::{Utf8Path, Utf8PathBuf};
+use semver::Version;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+use crate::penv::project::{current_dir, shell_environment, ProjectFile};
+use crate::penv::release::{Release, ReleaseChannel};
+use crate::penv::state::write_atomic;
+
+/// A value derived from penv's state, cached in the penv home so that commands run on
+/// every prompt don't have to load the state again while it's unchanged.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot<T> {
+    /// The [`state_key`] of the state the value was derived from.
+    pub key: String,
+    pub value: T,
+}
+
+impl<T: Serialize + DeserializeOwned> Snapshot<T> {
+    /// The path of the snapshot called `name` within the penv home.
+    pub fn path(home: &Utf8Path, name: &str) -> Utf8PathBuf {
+        home.join("snapshots").join(format!("{}.json", name))
+    }
+
+    /// Reads the snapshot at `path`, if it exists and was derived from the state
+    /// identified by `key`.
+    pub fn read(path: &Utf8Path, key: &str) -> Option<Self> {
+        let contents = fs::read_to_string(path).ok()?;
+        serde_json::from_str::<Self>(&contents)
+            .ok()
+            .filter(|snapshot| snapshot.key == key)
+    }
+
+    pub fn write(&self, path: &Utf8Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(path, &serde_json::to_string(self)?)?;
+
+        Ok(())
+    }
+}
+
+/// A version published by a repository, as last fetched from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublishedVersion {
+    pub version: Version,
+    /// Whether the release is a prerelease, by its flag on GitHub or its version.
+    pub prerelease: bool,
+}
+
+/// The snapshot of the versions published by each repository, which lets shell
+/// completions offer versions that aren't installed without accessing the network.
+///
+/// It's keyed by the penv version that wrote it rather than by penv's state, as the
+/// releases don't depend on the state.
+type PublishedVersions = Snapshot<BTreeMap<String, Vec<PublishedVersion>>>;
+
+/// Records the releases fetched from `repository_name`, leaving out drafts.
+pub fn record_published_versions(
+    home: &Utf8Path,
+    repository_name: &str,
+    releases: &[Release],
+) -> Result<()> {
+    let path = PublishedVersions::path(home, "releases");
+    let mut snapshot =
+        PublishedVersions::read(&path, env!("CARGO_PKG_VERSION")).unwrap_or(Snapshot {
+            key: env!("CARGO_PKG_VERSION").to_string(),
+            value: BTreeMap::new(),
+        });
+    let versions = releases
+        .iter()
+        .filter(|release| !release.draft)
+        .map(|release| PublishedVersion {
+            version: release.version.clone(),
+            prerelease: release.is_prerelease(),
+        })
+        .collect();
+    snapshot.value.insert(repository_name.to_string(), versions);
+
+    snapshot.write(&path)
+}
+
+/// The versions last fetched from any repository that are published on `channel`.
+pub fn published_versions(home: &Utf8Path, channel: ReleaseChannel) -> Vec<Version> {
+    let path = PublishedVersions::path(home, "releases");
+    let snapshot = match PublishedVersions::read(&path, env!("CARGO_PKG_VERSION")) {
+        Some(snapshot) => snapshot,
+        None => return Vec::new(),
+    };
+
+    snapshot
+        .value
+        .into_values()
+        .flatten()
+        .filter(|published| channel != ReleaseChannel::Stable || !published.prerelease)
+        .map(|published| published.version)
+        .collect()
+}
+
+/// Identifies the state that determines the effective environment: the penv state
+/// files, the nearest project file, the environment activated with `penv shell`, and
+/// the penv version reading them.
+///
+/// `extra` distinguishes values derived from the same state in different ways.
+pub fn state_key(home: &Utf8Path, extra: &[&str]) -> String {
+    let project_file = current_dir().ok().and_then(|dir| ProjectFile::find(&dir));
+    let state = format!(
+        "{}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}",
+        env!("CARGO_PKG_VERSION"),
+        modified(&home.join("penv.toml")),
+        modified(&home.join("cache.toml")),
+        project_file,
+        project_file.as_deref().and_then(modified),
+        shell_environment(),
+        extra,
+    );
+
+    hex::encode(Sha256::digest(state.as_bytes()))
+}
+
+/// The modification time of a file, if it exists.
+fn modified(path: &Utf8Path) -> Option<u128> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn published_versions_by_channel() {
+        let home = tempfile::tempdir().unwrap();
+        let home = Utf8Path::from_path(home.path()).unwrap();
+        let release = |version: &str, prerelease: bool, draft: bool| Release {
+            version: Version::parse(version).unwrap(),
+            body: None,
+            assets: Vec::new(),
+            name: version.to_string(),
+            prerelease,
+            draft,
+        };
+
+        assert!(published_versions(home, ReleaseChannel::All).is_empty());
+        record_published_versions(
+            home,
+            "penumbra-zone/penumbra",
+            &[
+                release("1.0.0", false, false),
+                release("1.1.0", true, false),
+                release("2.0.0-rc.1", false, false),
+                release("2.0.0", false, true),
+            ],
+        )
+        .unwrap();
+        record_published_versions(home, "fork/penumbra", &[release("1.0.0", false, false)])
+            .unwrap();
+
+        let versions = |channel| {
+            published_versions(home, channel)
+                .iter()
+                .map(Version::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(versions(ReleaseChannel::Stable), ["1.0.0", "1.0.0"]);
+        assert_eq!(
+            versions(ReleaseChannel::Prerelease),
+            ["1.0.0", "1.0.0", "1.1.0", "2.0.0-rc.1"]
+        );
+
+        // Fetching a repository again replaces its versions.
+        record_published_versions(home, "fork/penumbra", &[]).unwrap();
+        assert_eq!(versions(ReleaseChannel::Stable), ["1.0.0"]);
+    }
+}
@@ -0,0 +1,849 @@
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+use std::fs::{self, File, TryLockError};
+use std::io::Write as _;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{anyhow, Context as _, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{de::DeserializeOwned, Serialize};
+use toml::Table;
+
+use crate::penv::release::DEFAULT_REPOSITORY_NAME;
+
+/// The key recording which version of its schema a state file was written with.
+///
+/// Files written before the schema was versioned don't have it, and are version 0.
+pub const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// The keys of the paths in the state files. Paths within the penv home are stored
+/// relative to it, so that the home can be moved.
+const PATH_KEYS: [&str; 3] = ["root_dir", "local_filepath", "install_path"];
+
+/// Upgrades the contents of a state file in the given penv home from one schema version
+/// to the next.
+///
+/// Migrations may read other state files, but only ever change the contents passed to
+/// them.
+type Migration = fn(&Utf8Path, &mut Table) -> Result<()>;
+
+/// The files penv keeps its state in, within the penv home.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StateFile {
+    /// The environments and penv's settings.
+    Penv,
+    /// The installed releases.
+    Cache,
+}
+
+impl StateFile {
+    pub const ALL: [StateFile; 2] = [StateFile::Penv, StateFile::Cache];
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            StateFile::Penv => "penv.toml",
+            StateFile::Cache => "cache.toml",
+        }
+    }
+
+    pub fn path(&self, home: &Utf8Path) -> Utf8PathBuf {
+        home.join(self.file_name())
+    }
+
+    /// The migrations for this file, where the migration at index `i` upgrades the
+    /// contents from schema version `i` to `i + 1`.
+    fn migrations(&self) -> &'static [Migration] {
+        match self {
+            StateFile::Penv => &[
+                unversioned,
+                drop_installed_releases,
+                record_environment_repositories,
+                relative_paths,
+                tag_patterns_by_repository,
+            ],
+            StateFile::Cache => &[
+                unversioned,
+                reconcile_installed_releases,
+                record_release_repositories,
+                relative_paths,
+            ],
+        }
+    }
+
+    /// The schema version this penv reads and writes.
+    pub fn schema_version(&self) -> u32 {
+        self.migrations().len() as u32
+    }
+
+    /// Serializes `value` as the contents of this file in the penv home, tagged with the
+    /// current schema version.
+    pub fn serialize_contents<T: Serialize>(&self, home: &Utf8Path, value: &T) -> Result<String> {
+        let mut table = Table::try_from(value)?;
+        map_paths(&mut table, &|path| relative_path(path, home));
+
+        Ok(format!(
+            "{} = {}\n{}",
+            SCHEMA_VERSION_KEY,
+            self.schema_version(),
+            toml::to_string(&table)?
+        ))
+    }
+
+    /// Reads this file from the penv home, or `None` if it doesn't exist.
+    ///
+    /// Files written with an older schema are migrated first, and the migrated contents
+    /// written back, keeping a backup of the original next to it.
+    pub fn load<T: DeserializeOwned>(&self, home: &Utf8Path) -> Result<Option<T>> {
+        let path = self.path(home);
+        let contents = match self.read(home)? {
+            Some(contents) => contents,
+            None => return Ok(None),
+        };
+
+        if contents.version < self.schema_version() {
+            // Writing back the migrated file requires the lock, but read-only commands
+            // shouldn't wait for it, and can just as well use the migrated contents.
+            let _lock = match HomeLock::is_held() {
+                true => None,
+                false => match HomeLock::try_acquire(home)? {
+                    Some(lock) => Some(lock),
+                    None => {
+                        tracing::debug!("{} is locked, not writing back migrated {}", home, path);
+                        return contents.deserialize(&path).map(Some);
+                    }
+                },
+            };
+
+            if !path.is_file() {
+                write_atomic(&path, &self.serialize_contents(home, &contents.table)?)
+                    .with_context(|| format!("Failed to write {}", path))?;
+                eprintln!(
+                    "created {} from the installed releases listed in {}",
+                    path,
+                    StateFile::Penv.path(home)
+                );
+                return contents.deserialize(&path).map(Some);
+            }
+
+            let backup_path = self.backup_path(home, contents.version);
+            fs::copy(&path, &backup_path)
+                .with_context(|| format!("Failed to back up {} to {}", path, backup_path))?;
+            write_atomic(&path, &self.serialize_contents(home, &contents.table)?)
+                .with_context(|| format!("Failed to write migrated {}", path))?;
+            eprintln!(
+                "migrated {} from schema version {} to {}, the previous version was saved to {}",
+                path,
+                contents.version,
+                self.schema_version(),
+                backup_path
+            );
+        }
+
+        contents.deserialize(&path).map(Some)
+    }
+
+    /// Reads and parses this file from the penv home, migrating its contents to the
+    /// current schema version in memory, or `None` if it doesn't exist.
+    ///
+    /// Relative paths in the contents are resolved against the home.
+    pub fn read(&self, home: &Utf8Path) -> Result<Option<StateContents>> {
+        let path = self.path(home);
+        if !path.is_file() {
+            // Before version 2, the installed releases were kept in `penv.toml` as well,
+            // which may be the only copy left. It's reconciled into an empty cache.
+            if *self == StateFile::Cache && !penv_installed_releases(home)?.is_empty() {
+                let mut table = Table::new();
+                self.migrate(home, &path, &mut table, 0)?;
+                return Ok(Some(StateContents { table, version: 0 }));
+            }
+            return Ok(None);
+        }
+
+        let raw = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+        let mut table = raw
+            .parse::<Table>()
+            .map_err(|e| anyhow!("{} is not valid TOML: {}", path, e))?;
+
+        let version = match table.remove(SCHEMA_VERSION_KEY) {
+            None => 0,
+            Some(toml::Value::Integer(version)) => u32::try_from(version)
+                .map_err(|_| anyhow!("{} has an invalid schema version {}", path, version))?,
+            Some(value) => {
+                return Err(anyhow!(
+                    "{} has an invalid schema version {}, expected an integer",
+                    path,
+                    value
+                ))
+            }
+        };
+        if version > self.schema_version() {
+            return Err(anyhow!(
+                "{} has schema version {}, but this penv only supports up to version {}; upgrade penv to use this home",
+                path,
+                version,
+                self.schema_version()
+            ));
+        }
+
+        self.migrate(home, &path, &mut table, version)?;
+
+        Ok(Some(StateContents { table, version }))
+    }
+
+    /// Migrates the contents of this file from `version` to the current schema version,
+    /// and resolves the relative paths in them against the home.
+    fn migrate(
+        &self,
+        home: &Utf8Path,
+        path: &Utf8Path,
+        table: &mut Table,
+        version: u32,
+    ) -> Result<()> {
+        for (from, migration) in self.migrations().iter().enumerate().skip(version as usize) {
+            migration(home, table).with_context(|| {
+                format!(
+                    "Failed to migrate {} from schema version {} to {}",
+                    path,
+                    from,
+                    from + 1
+                )
+            })?;
+        }
+        map_paths(table, &|path| {
+            path.is_relative().then(|| home.join(path).into_string())
+        });
+
+        Ok(())
+    }
+
+    /// Where the contents of this file are saved before migrating from `version`.
+    pub fn backup_path(&self, home: &Utf8Path, version: u32) -> Utf8PathBuf {
+        home.join(format!("{}.v{}.bak", self.file_name(), version))
+    }
+}
+
+impl Display for StateFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file_name())
+    }
+}
+
+/// The contents of a state file, migrated to the current schema version.
+#[derive(Debug)]
+pub struct StateContents {
+    pub table: Table,
+    /// The schema version the file was written with.
+    pub version: u32,
+}
+
+impl StateContents {
+    fn deserialize<T: DeserializeOwned>(self, path: &Utf8Path) -> Result<T> {
+        self.table
+            .try_into()
+            .map_err(|e| anyhow!("{} is invalid: {}", path, e.to_string().trim_end()))
+    }
+}
+
+/// Replaces the file at `path` with `contents`, such that readers see either the old or
+/// the new contents, and never a partially written file.
+///
+/// An existing file keeps its permissions; a new file is readable by everyone, like one
+/// created with [`fs::write`] under the usual umask.
+pub fn write_atomic(path: &Utf8Path, contents: &str) -> Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("{} has no parent directory", path))?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("Failed to create a temporary file in {}", dir))?;
+    // Temporary files are only readable by their owner.
+    let permissions = match fs::metadata(path) {
+        Ok(metadata) => Some(metadata.permissions()),
+        Err(_) => default_permissions(),
+    };
+    if let Some(permissions) = permissions {
+        file.as_file()
+            .set_permissions(permissions)
+            .with_context(|| {
+                format!("Failed to set the permissions of {}", file.path().display())
+            })?;
+    }
+    file.write_all(contents.as_bytes())?;
+    file.as_file().sync_all()?;
+    file.persist(path)
+        .with_context(|| format!("Failed to replace {}", path))?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn default_permissions() -> Option<fs::Permissions> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    Some(fs::Permissions::from_mode(0o644))
+}
+
+#[cfg(not(unix))]
+fn default_permissions() -> Option<fs::Permissions> {
+    None
+}
+
+/// Set while this process holds the lock on the penv home.
+static LOCK_HELD: AtomicBool = AtomicBool::new(false);
+
+/// An advisory lock on the penv home, held by commands that change penv's state for as
+/// long as they run, and released when dropped.
+///
+/// Readers don't take the lock. State files are replaced atomically, so they always see
+/// a complete state, from either before or after a change.
+#[derive(Debug)]
+pub struct HomeLock {
+    _file: File,
+}
+
+impl HomeLock {
+    /// Acquires the lock on the penv home, waiting for another process to release it.
+    pub fn acquire(home: &Utf8Path) -> Result<Self> {
+        let file = Self::open(home)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                eprintln!(
+                    "waiting for another penv process to finish changing {}...",
+                    home
+                );
+                file.lock()
+                    .with_context(|| format!("Failed to lock {}", home))?;
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(anyhow!(e).context(format!("Failed to lock {}", home)))
+            }
+        }
+
+        LOCK_HELD.store(true, Ordering::SeqCst);
+        Ok(Self { _file: file })
+    }
+
+    /// Acquires the lock on the penv home, or returns `None` if another process holds it.
+    pub fn try_acquire(home: &Utf8Path) -> Result<Option<Self>> {
+        let file = Self::open(home)?;
+        match file.try_lock() {
+            Ok(()) => {
+                LOCK_HELD.store(true, Ordering::SeqCst);
+                Ok(Some(Self { _file: file }))
+            }
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => {
+                Err(anyhow!(e).context(format!("Failed to lock {}", home)))
+            }
+        }
+    }
+
+    /// Whether this process holds the lock on the penv home.
+    pub fn is_held() -> bool {
+        LOCK_HELD.load(Ordering::SeqCst)
+    }
+
+    fn open(home: &Utf8Path) -> Result<File> {
+        let path = home.join("penv.lock");
+        File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file {}", path))
+    }
+}
+
+impl Drop for HomeLock {
+    fn drop(&mut self) {
+        // The lock itself is released when the file is closed.
+        LOCK_HELD.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Files without a schema version only lack the version key itself.
+fn unversioned(_home: &Utf8Path, _table: &mut Table) -> Result<()> {
+    Ok(())
+}
+
+/// `penv.toml` used to keep a copy of the installed releases, which live in
+/// `cache.toml` only from version 2 on. The copies are reconciled by
+/// [`reconcile_installed_releases`], which is why `Cache` has to be loaded before `Penv`.
+fn drop_installed_releases(_home: &Utf8Path, table: &mut Table) -> Result<()> {
+    table.remove("cache");
+    Ok(())
+}
+
+/// Adds the installed releases only listed in the copy in `penv.toml` to `cache.toml`,
+/// if their files are still there. The copies drifted apart whenever only one of them
+/// was updated, for example by `penv cache reset` or `penv cache delete`.
+fn reconcile_installed_releases(home: &Utf8Path, table: &mut Table) -> Result<()> {
+    let penv_releases = penv_installed_releases(home)?;
+
+    let releases = table
+        .entry("installed_releases")
+        .or_insert_with(|| toml::Value::Array(Vec::new()))
+        .as_array_mut()
+        .ok_or_else(|| anyhow!("installed_releases is not an array"))?;
+    for release in penv_releases {
+        let id = release_id(&release);
+        if id.is_some() && !releases.iter().any(|r| release_id(r) == id) && is_on_disk(&release) {
+            releases.push(release);
+        }
+    }
+
+    Ok(())
+}
+
+/// The copy of the installed releases in a `penv.toml` from before version 2, if any.
+fn penv_installed_releases(home: &Utf8Path) -> Result<Vec<toml::Value>> {
+    let penv_path = StateFile::Penv.path(home);
+    match fs::read_to_string(&penv_path) {
+        Ok(contents) => Ok(contents
+            .parse::<Table>()
+            .map_err(|e| anyhow!("{} is not valid TOML: {}", penv_path, e))?
+            .get("cache")
+            .and_then(|cache| cache.get("installed_releases"))
+            .and_then(|releases| releases.as_array())
+            .cloned()
+            .unwrap_or_default()),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+/// Until version 3, `penv.toml` had a single `repository_name` that all releases were
+/// installed from. It's recorded with each binary environment instead.
+fn record_environment_repositories(_home: &Utf8Path, table: &mut Table) -> Result<()> {
+    let repository_name = table
+        .remove("repository_name")
+        .unwrap_or_else(|| toml::Value::String(DEFAULT_REPOSITORY_NAME.to_string()));
+
+    let environments = table
+        .get_mut("environments")
+        .and_then(|environments| environments.get_mut("environments"))
+        .and_then(|environments| environments.as_array_mut());
+    for environment in environments.into_iter().flatten() {
+        if environment.get("type").and_then(|t| t.as_str()) != Some("BinaryEnvironment") {
+            continue;
+        }
+        if let Some(args) = environment.get_mut("args").and_then(|a| a.as_table_mut()) {
+            args.entry("repository_name")
+                .or_insert_with(|| repository_name.clone());
+        }
+    }
+
+    Ok(())
+}
+
+/// Records the repository each binary release was installed from, which until version
+/// 3 was the single `repository_name` in `penv.toml`. That's only read before `Penv` is
+/// migrated as well, which is why `Cache` has to be loaded first.
+fn record_release_repositories(home: &Utf8Path, table: &mut Table) -> Result<()> {
+    let penv_path = StateFile::Penv.path(home);
+    let repository_name = match fs::read_to_string(&penv_path) {
+        Ok(contents) => contents
+            .parse::<Table>()
+            .map_err(|e| anyhow!("{} is not valid TOML: {}", penv_path, e))?
+            .remove("repository_name"),
+        Err(_) => None,
+    }
+    .unwrap_or_else(|| toml::Value::String(DEFAULT_REPOSITORY_NAME.to_string()));
+
+    let releases = table
+        .get_mut("installed_releases")
+        .and_then(|releases| releases.as_array_mut());
+    for release in releases.into_iter().flatten() {
+        if release.get("type").and_then(|t| t.as_str()) != Some("Binary") {
+            continue;
+        }
+        if let Some(args) = release.get_mut("args").and_then(|a| a.as_table_mut()) {
+            args.entry("repository_name")
+                .or_insert_with(|| repository_name.clone());
+        }
+    }
+
+    Ok(())
+}
+
+/// Until version 4, paths were stored as absolute paths, which broke when the penv
+/// home was moved. They're stored relative to the home they were written in instead,
+/// which is the `home_dir` recorded in `penv.toml`.
+fn relative_paths(home: &Utf8Path, table: &mut Table) -> Result<()> {
+    let penv_path = StateFile::Penv.path(home);
+    let recorded_home = match table.get("home_dir") {
+        Some(home_dir) => home_dir.as_str().map(Utf8PathBuf::from),
+        None => match fs::read_to_string(&penv_path) {
+            Ok(contents) => contents
+                .parse::<Table>()
+                .map_err(|e| anyhow!("{} is not valid TOML: {}", penv_path, e))?
+                .get("home_dir")
+                .and_then(|home_dir| home_dir.as_str())
+                .map(Utf8PathBuf::from),
+            Err(_) => None,
+        },
+    };
+
+    map_paths(table, &|path| {
+        recorded_home
+            .as_deref()
+            .and_then(|recorded_home| relative_path(path, recorded_home))
+            .or_else(|| relative_path(path, home))
+    });
+
+    Ok(())
+}
+
+/// Until version 5, `penv.toml` had a single `tag_pattern` used for every repository,
+/// so that a pattern set for a fork broke upstream's tags. It's kept for the repositories
+/// in use at the time, those of the environments and installed releases, and for the
+/// default repository if there are none.
+fn tag_patterns_by_repository(home: &Utf8Path, table: &mut Table) -> Result<()> {
+    let tag_pattern = match table.remove("tag_pattern") {
+        // Repositories without a pattern use the default one anyway.
+        Some(tag_pattern) if tag_pattern.as_str() != Some("v{version}") => tag_pattern,
+        _ => return Ok(()),
+    };
+
+    let mut repositories = BTreeSet::new();
+    let environments = table
+        .get("environments")
+        .and_then(|environments| environments.get("environments"))
+        .and_then(|environments| environments.as_array());
+    for environment in environments.into_iter().flatten() {
+        if let Some(repository_name) = environment
+            .get("args")
+            .and_then(|args| args.get("repository_name"))
+            .and_then(|name| name.as_str())
+        {
+            repositories.insert(repository_name.to_string());
+        }
+    }
+
+    let cache_path = StateFile::Cache.path(home);
+    let releases = match fs::read_to_string(&cache_path) {
+        Ok(contents) => contents
+            .parse::<Table>()
+            .map_err(|e| anyhow!("{} is not valid TOML: {}", cache_path, e))?
+            .remove("installed_releases")
+            .and_then(|releases| releases.as_array().cloned())
+            .unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
+    for release in releases {
+        if release.get("type").and_then(|t| t.as_str()) != Some("Binary") {
+            continue;
+        }
+        let repository_name = release
+            .get("args")
+            .and_then(|args| args.get("repository_name"))
+            .and_then(|name| name.as_str())
+            .unwrap_or(DEFAULT_REPOSITORY_NAME);
+        repositories.insert(repository_name.to_string());
+    }
+
+    if repositories.is_empty() {
+        repositories.insert(DEFAULT_REPOSITORY_NAME.to_string());
+    }
+    let tag_patterns = repositories
+        .into_iter()
+        .map(|repository_name| (repository_name, tag_pattern.clone()))
+        .collect::<Table>();
+    table.insert("tag_patterns".to_string(), toml::Value::Table(tag_patterns));
+
+    Ok(())
+}
+
+/// The path relative to `home`, if it's within it.
+fn relative_path(path: &Utf8Path, home: &Utf8Path) -> Option<String> {
+    path.strip_prefix(home)
+        .ok()
+        .map(|path| path.as_str().to_string())
+}
+
+/// Replaces each path in `table` for which `f` returns a new path.
+fn map_paths(table: &mut Table, f: &impl Fn(&Utf8Path) -> Option<String>) {
+    for (key, value) in table.iter_mut() {
+        match value {
+            toml::Value::String(path) if PATH_KEYS.contains(&key.as_str()) => {
+                if let Some(mapped) = f(Utf8Path::new(path)) {
+                    *path = mapped;
+                }
+            }
+            value => map_nested_paths(value, f),
+        }
+    }
+}
+
+fn map_nested_paths(value: &mut toml::Value, f: &impl Fn(&Utf8Path) -> Option<String>) {
+    match value {
+        toml::Value::Table(table) => map_paths(table, f),
+        toml::Value::Array(values) => {
+            for value in values {
+                map_nested_paths(value, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Identifies an installed release by its type, and its version or repository URL.
+fn release_id(release: &toml::Value) -> Option<(&str, &str)> {
+    let args = release.get("args")?;
+    let id = args.get("version").or_else(|| args.get("url"))?;
+    Some((release.get("type")?.as_str()?, id.as_str()?))
+}
+
+/// Whether the installation directory and all assets of an installed release exist.
+fn is_on_disk(release: &toml::Value) -> bool {
+    let args = match release.get("args") {
+        Some(args) => args,
+        None => return false,
+    };
+    let dir = args.get("root_dir").or_else(|| args.get("install_path"));
+    let assets = args
+        .get("assets")
+        .and_then(|assets| assets.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    dir.and_then(|dir| dir.as_str())
+        .is_some_and(|dir| Utf8Path::new(dir).is_dir())
+        && assets.iter().all(|asset| {
+            asset
+                .get("local_filepath")
+                .and_then(|path| path.as_str())
+                .is_some_and(|path| Utf8Path::new(path).is_file())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomic_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let mode = |path: &Utf8Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let new = dir.join("new.toml");
+        write_atomic(&new, "a = 1\n").unwrap();
+        assert_eq!(mode(&new), 0o644);
+
+        let existing = dir.join("existing.toml");
+        fs::write(&existing, "a = 1\n").unwrap();
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o640)).unwrap();
+        write_atomic(&existing, "a = 2\n").unwrap();
+        assert_eq!(mode(&existing), 0o640);
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "a = 2\n");
+    }
+
+    #[test]
+    fn migrate_unversioned_file() {
+        let home = tempfile::tempdir().unwrap();
+        let home = Utf8Path::from_path(home.path()).unwrap();
+        let contents = "[[installed_releases]]\ntype = \"Binary\"\n\n[installed_releases.args]\nversion = \"1.0.0\"\nbody = \"notes\"\nname = \"1.0.0\"\nroot_dir = \"/tmp/fake\"\nassets = []\n";
+        fs::write(StateFile::Cache.path(home), contents).unwrap();
+
+        let data = StateFile::Cache
+            .load::<crate::penv::cache::cache::CacheData>(home)
+            .unwrap()
+            .unwrap();
+        assert_eq!(data.installed_releases.len(), 1);
+
+        // The original is backed up, and the file rewritten with the current version.
+        assert_eq!(
+            fs::read_to_string(StateFile::Cache.backup_path(home, 0)).unwrap(),
+            contents
+        );
+        let migrated = StateFile::Cache.read(home).unwrap().unwrap();
+        assert_eq!(migrated.version, StateFile::Cache.schema_version());
+
+        // Files from a newer penv are rejected rather than misread.
+        fs::write(
+            StateFile::Cache.path(home),
+            format!(
+                "schema_version = {}\n",
+                StateFile::Cache.schema_version() + 1
+            ),
+        )
+        .unwrap();
+        assert!(StateFile::Cache.read(home).is_err());
+    }
+
+    #[test]
+    fn lock_home() {
+        let home = tempfile::tempdir().unwrap();
+        let home = Utf8Path::from_path(home.path()).unwrap();
+
+        let lock = HomeLock::try_acquire(home).unwrap();
+        assert!(lock.is_some());
+        assert!(HomeLock::try_acquire(home).unwrap().is_none());
+        drop(lock);
+        assert!(HomeLock::try_acquire(home).unwrap().is_some());
+    }
+
+    #[test]
+    fn reconcile_installed_releases() {
+        let home = tempfile::tempdir().unwrap();
+        let home = Utf8Path::from_path(home.path()).unwrap();
+        let release = |version: &str| {
+            format!(
+                "[[cache.installed_releases]]\ntype = \"Binary\"\n\n[cache.installed_releases.args]\nversion = \"{version}\"\nbody = \"notes\"\nname = \"{version}\"\nroot_dir = \"{home}/versions/{version}\"\nassets = []\n"
+            )
+        };
+        // 1.0.0 was removed from the cache by `penv cache reset`, while 2.0.0 was
+        // deleted from disk by `penv cache delete`.
+        fs::create_dir_all(home.join("versions/1.0.0")).unwrap();
+        fs::write(
+            StateFile::Penv.path(home),
+            format!(
+                "schema_version = 1\nrepository_name = \"penumbra-zone/penumbra\"\nhome_dir = \"{home}\"\nenvironments = {{ environments = [] }}\n{}{}",
+                release("1.0.0"),
+                release("2.0.0")
+            ),
+        )
+        .unwrap();
+        fs::write(
+            StateFile::Cache.path(home),
+            "schema_version = 1\ninstalled_releases = []\n",
+        )
+        .unwrap();
+
+        let data = StateFile::Cache
+            .load::<crate::penv::cache::cache::CacheData>(home)
+            .unwrap()
+            .unwrap();
+        let versions = data
+            .installed_releases
+            .iter()
+            .map(|release| release.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(versions, ["1.0.0"]);
+
+        let penv = StateFile::Penv.read(home).unwrap().unwrap();
+        assert!(!penv.table.contains_key("cache"));
+
+        // Without a `cache.toml`, it's created from the copy in `penv.toml`.
+        fs::remove_file(StateFile::Cache.path(home)).unwrap();
+        let data = StateFile::Cache
+            .load::<crate::penv::cache::cache::CacheData>(home)
+            .unwrap()
+            .unwrap();
+        assert_eq!(data.installed_releases.len(), 1);
+        assert_eq!(data.installed_releases[0].to_string(), "1.0.0");
+        let cache = StateFile::Cache.read(home).unwrap().unwrap();
+        assert_eq!(cache.version, StateFile::Cache.schema_version());
+        assert_eq!(
+            cache.table["installed_releases"].as_array().unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn record_repositories() {
+        let home = tempfile::tempdir().unwrap();
+        let home = Utf8Path::from_path(home.path()).unwrap();
+        fs::write(
+            StateFile::Penv.path(home),
+            format!(
+                "schema_version = 2\nrepository_name = \"example/fork\"\nhome_dir = \"{home}\"\n\n[[environments.environments]]\ntype = \"BinaryEnvironment\"\n\n[environments.environments.args]\npinned_version = \"1.0.0\"\n"
+            ),
+        )
+        .unwrap();
+        fs::write(
+            StateFile::Cache.path(home),
+            "schema_version = 2\n\n[[installed_releases]]\ntype = \"Binary\"\n\n[installed_releases.args]\nversion = \"1.0.0\"\nbody = \"notes\"\nname = \"1.0.0\"\nroot_dir = \"/tmp/fake\"\nassets = []\n",
+        )
+        .unwrap();
+
+        // The cache is migrated first, while `penv.toml` still has the repository.
+        let cache = StateFile::Cache.read(home).unwrap().unwrap();
+        let release = &cache.table["installed_releases"][0]["args"];
+        assert_eq!(release["repository_name"].as_str(), Some("example/fork"));
+
+        let penv = StateFile::Penv.read(home).unwrap().unwrap();
+        assert!(!penv.table.contains_key("repository_name"));
+        let environment = &penv.table["environments"]["environments"][0]["args"];
+        assert_eq!(
+            environment["repository_name"].as_str(),
+            Some("example/fork")
+        );
+    }
+
+    #[test]
+    fn relative_paths() {
+        let home = tempfile::tempdir().unwrap();
+        let home = Utf8Path::from_path(home.path()).unwrap();
+        // The home was restored from a backup of `/old/home`.
+        fs::write(
+            StateFile::Penv.path(home),
+            "schema_version = 3\nhome_dir = \"/old/home\"\n\n[environments]\nenvironments = []\n",
+        )
+        .unwrap();
+        fs::write(
+            StateFile::Cache.path(home),
+            "schema_version = 3\n\n[[installed_releases]]\ntype = \"Binary\"\n\n[installed_releases.args]\nrepository_name = \"penumbra-zone/penumbra\"\nversion = \"1.0.0\"\nbody = \"notes\"\nname = \"1.0.0\"\nroot_dir = \"/old/home/versions/1.0.0\"\n\n[[installed_releases.args.assets]]\ntarget_arch = \"x86_64-unknown-linux-gnu\"\nlocal_filepath = \"/elsewhere/pcli\"\n",
+        )
+        .unwrap();
+
+        let data = StateFile::Cache
+            .load::<crate::penv::cache::cache::CacheData>(home)
+            .unwrap()
+            .unwrap();
+        let release = match &data.installed_releases[0] {
+            crate::penv::release::InstalledRelease::Binary(release) => release,
+            _ => panic!("expected a binary release"),
+        };
+        // Paths within the home resolve against where it is now, others are kept.
+        assert_eq!(release.root_dir, home.join("versions/1.0.0"));
+        assert_eq!(release.assets[0].local_filepath, "/elsewhere/pcli");
+
+        let written = fs::read_to_string(StateFile::Cache.path(home)).unwrap();
+        assert!(written.contains("root_dir = \"versions/1.0.0\""));
+        assert!(written.contains("local_filepath = \"/elsewhere/pcli\""));
+    }
+
+    #[test]
+    fn tag_patterns_by_repository() {
+        let home = tempfile::tempdir().unwrap();
+        let home = Utf8Path::from_path(home.path()).unwrap();
+        fs::write(
+            StateFile::Penv.path(home),
+            format!(
+                "schema_version = 4\ntag_pattern = \"penumbra-{{version}}\"\nhome_dir = \"{home}\"\n\n[[environments.environments]]\ntype = \"BinaryEnvironment\"\n\n[environments.environments.args]\nrepository_name = \"example/fork\"\npinned_version = \"1.0.0\"\n"
+            ),
+        )
+        .unwrap();
+        fs::write(
+            StateFile::Cache.path(home),
+            "schema_version = 4\n\n[[installed_releases]]\ntype = \"Binary\"\n\n[installed_releases.args]\nrepository_name = \"example/other\"\nversion = \"1.0.0\"\nbody = \"notes\"\nname = \"1.0.0\"\nroot_dir = \"versions/1.0.0\"\nassets = []\n",
+        )
+        .unwrap();
+
+        let penv = StateFile::Penv.read(home).unwrap().unwrap();
+        assert!(!penv.table.contains_key("tag_pattern"));
+        let tag_patterns = penv.table["tag_patterns"].as_table().unwrap();
+        assert_eq!(
+            tag_patterns.keys().collect::<Vec<_>>(),
+            ["example/fork", "example/other"]
+        );
+        assert_eq!(
+            tag_patterns["example/fork"].as_str(),
+            Some("penumbra-{version}")
+        );
+
+        // The default pattern doesn't need to be recorded for any repository.
+        fs::write(
+            StateFile::Penv.path(home),
+            format!("schema_version = 4\ntag_pattern = \"v{{version}}\"\nhome_dir = \"{home}\"\n\n[environments]\nenvironments = []\n"),
+        )
+        .unwrap();
+        let penv = StateFile::Penv.read(home).unwrap().unwrap();
+        assert!(!penv.table.contains_key("tag_pattern"));
+        assert!(!penv.table.contains_key("tag_patterns"));
+    }
+}